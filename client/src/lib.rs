@@ -1,9 +1,11 @@
 use std::convert::TryInto;
+use std::sync::Arc;
 
 use rand::SeedableRng;
 use rand_chacha::ChaCha20Rng;
-use spiral_rs::{client::*, discrete_gaussian::*, util::*};
+use spiral_rs::{client::*, discrete_gaussian::*, params::*, util::*};
 use wasm_bindgen::prelude::*;
+use zeroize::Zeroize;
 
 const UUID_V4_LEN: usize = 36;
 
@@ -18,11 +20,10 @@ macro_rules! console_log {
     ($($t:tt)*) => (log(&format_args!($($t)*).to_string()))
 }
 
-// Container class for a static lifetime Client
-// Avoids a lifetime in the return signature of bound Rust functions
+// Container class for a Client, so bound Rust functions can hand it to JS
 #[wasm_bindgen]
 pub struct WrappedClient {
-    client: &'static mut Client<'static>,
+    client: Client,
 }
 
 // Very simply test to ensure random generation is not obviously biased.
@@ -44,58 +45,90 @@ fn _dg_seems_okay() {
     assert!(f64::abs(mean) < std_dev_of_mean * 5f64);
 }
 
-// Initializes a client; can optionally take in a set of parameters
+// Initializes a client; can optionally take in a set of parameters,
+// such as the JSON served by the server at `/params`
 #[wasm_bindgen]
-pub fn initialize(json_params: Option<String>) -> WrappedClient {
+pub fn initialize(json_params: Option<String>) -> Result<WrappedClient, JsValue> {
     // spiral_rs::ntt::test::ntt_correct();
-    let cfg = r#"
-        {'n': 2,
-        'nu_1': 10,
-        'nu_2': 6,
-        'p': 512,
-        'q2_bits': 21,
-        's_e': 85.83255142749422,
-        't_gsw': 10,
-        't_conv': 4,
-        't_exp_left': 16,
-        't_exp_right': 56,
-        'instances': 11,
-        'db_item_size': 100000 }
-    "#;
-    let mut cfg = cfg.replace("'", "\"");
+    let params = get_params(json_params)?;
+    let client = Client::init(&params);
+
+    Ok(WrappedClient { client })
+}
+
+// Rebuilds a client from the output of `export_secret_key`, under the same parameters
+#[wasm_bindgen]
+pub fn initialize_from_secret_key(
+    json_params: Option<String>,
+    secret_key: Box<[u8]>,
+) -> Result<WrappedClient, JsValue> {
+    let params = get_params(json_params)?;
+    match Client::from_secret_key(&params, &*secret_key) {
+        Ok(client) => Ok(WrappedClient { client }),
+        Err(e) => Err(JsValue::from_str(&e.to_string())),
+    }
+}
+
+fn get_params(json_params: Option<String>) -> Result<Arc<Params>, JsValue> {
+    let mut cfg = CFG_16_100000.replace("'", "\"");
     if json_params.is_some() {
         cfg = json_params.unwrap();
     }
+    match try_params_from_json(&cfg) {
+        Ok(params) => Ok(Arc::new(params)),
+        Err(e) => Err(JsValue::from_str(&e.to_string())),
+    }
+}
 
-    let params = Box::leak(Box::new(params_from_json(&cfg)));
-    let client = Box::leak(Box::new(Client::init(params)));
-
-    WrappedClient { client }
+#[wasm_bindgen]
+pub fn export_secret_key(c: &WrappedClient) -> Box<[u8]> {
+    c.client.export_secret_key().into_boxed_slice()
 }
 
 #[wasm_bindgen]
-pub fn generate_keys(c: &mut WrappedClient, seed: Box<[u8]>, generate_pub_params: bool) -> Option<Box<[u8]>> {
+pub fn generate_keys(c: &mut WrappedClient, mut seed: Box<[u8]>, generate_pub_params: bool) -> Option<Box<[u8]>> {
+    let mut seed_copy: Seed = (*seed).try_into().unwrap();
+    seed.zeroize();
     if generate_pub_params {
-        Some(c.client.generate_keys_from_seed((*seed).try_into().unwrap()).serialize().into_boxed_slice())
+        Some(c.client.generate_keys_from_seed(&mut seed_copy).serialize().into_boxed_slice())
     } else {
-        c.client.generate_secret_keys_from_seed((*seed).try_into().unwrap());
+        c.client.generate_secret_keys_from_seed(&mut seed_copy);
         None
     }
 }
 
+// The id a server that addresses setups by content assigns to these public parameters
+#[wasm_bindgen]
+pub fn setup_id(pub_params: Box<[u8]>) -> String {
+    spiral_rs::client::setup_id(&pub_params)
+}
+
+// Precomputes up to `count` queries ahead of time, so that `generate_query` is fast once the
+// target is known
 #[wasm_bindgen]
-pub fn generate_query(c: &mut WrappedClient, id: &str, idx_target: usize) -> Box<[u8]> {
+pub fn precompute_queries(c: &mut WrappedClient, count: usize) {
+    c.client.precompute_queries(count);
+}
+
+#[wasm_bindgen]
+pub fn generate_query(c: &mut WrappedClient, id: &str, idx_target: usize) -> Result<Box<[u8]>, JsValue> {
     assert_eq!(id.len(), UUID_V4_LEN);
-    let query = c.client.generate_query(idx_target);
+    let query = match c.client.generate_query_from_pool(idx_target) {
+        Ok(query) => query,
+        Err(e) => return Err(JsValue::from_str(&e.to_string())),
+    };
     let mut query_buf = query.serialize();
     let mut full_query_buf = id.as_bytes().to_vec();
     full_query_buf.append(&mut query_buf);
-    full_query_buf.into_boxed_slice()
+    Ok(full_query_buf.into_boxed_slice())
 }
 
 #[wasm_bindgen]
-pub fn decode_response(c: &mut WrappedClient, data: Box<[u8]>) -> Box<[u8]> {
-    c.client.decode_response(&*data).into_boxed_slice()
+pub fn decode_response(c: &mut WrappedClient, data: Box<[u8]>) -> Result<Box<[u8]>, JsValue> {
+    match c.client.decode_response(&*data) {
+        Ok(result) => Ok(result.into_boxed_slice()),
+        Err(e) => Err(JsValue::from_str(&e.to_string())),
+    }
 }
 
 #[cfg(test)]
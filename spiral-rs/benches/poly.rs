@@ -1,9 +1,10 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use spiral_rs::poly::*;
 use spiral_rs::util::*;
+use std::sync::Arc;
 
 fn criterion_benchmark(c: &mut Criterion) {
-    let params = get_test_params();
+    let params = Arc::new(get_test_params());
     let mut m1 = PolyMatrixRaw::random(&params, 10, 10);
     let mut m2 = PolyMatrixNTT::random(&params, 10, 10);
     let m3 = PolyMatrixNTT::random(&params, 10, 10);
@@ -10,6 +10,7 @@ use spiral_rs::params::*;
 use spiral_rs::poly::*;
 use spiral_rs::server::*;
 use spiral_rs::util::*;
+use std::sync::Arc;
 use std::time::Duration;
 
 pub fn generate_random_incorrect_db(params: &Params) -> AlignedMemory64 {
@@ -37,14 +38,14 @@ fn test_full_processing(group: &mut BenchmarkGroup<WallTime>) {
     for i in 0..names.len() {
         let name = names[i];
         let cfg = cfgs[i];
-        let params = params_from_json(&cfg.replace("'", "\""));
+        let params = Arc::new(params_from_json(&cfg.replace("'", "\"")));
         let mut seeded_rng = get_seeded_rng();
 
         let target_idx = seeded_rng.gen::<usize>() % (params.db_dim_1 + params.db_dim_2);
 
         let mut client = Client::init(&params);
         let public_params = client.generate_keys();
-        let query = client.generate_query(target_idx);
+        let query = client.generate_query(target_idx).unwrap();
 
         println!("Generating database...");
         let db = generate_random_incorrect_db(&params);
@@ -69,7 +70,7 @@ fn criterion_benchmark(c: &mut Criterion) {
         .sample_size(10)
         .measurement_time(Duration::from_secs(30));
 
-    let params = get_expansion_testing_params();
+    let params = Arc::new(get_expansion_testing_params());
     let v_neg1 = params.get_v_neg1();
     let mut rng = get_chacha_rng();
     let mut rng_pub = get_chacha_rng();
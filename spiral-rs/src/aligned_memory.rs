@@ -1,10 +1,13 @@
 use std::{
     alloc::{alloc_zeroed, dealloc, Layout},
+    io,
     mem::size_of,
     ops::{Index, IndexMut},
     slice::{from_raw_parts, from_raw_parts_mut},
 };
 
+use zeroize::Zeroize;
+
 const ALIGN_SIMD: usize = 64; // enough to support AVX-512
 pub type AlignedMemory64 = AlignedMemory<ALIGN_SIMD>;
 
@@ -12,6 +15,8 @@ pub struct AlignedMemory<const ALIGN: usize> {
     p: *mut u64,
     sz_u64: usize,
     layout: Layout,
+    locked: bool,
+    secret: bool,
 }
 
 impl<const ALIGN: usize> AlignedMemory<{ ALIGN }> {
@@ -28,9 +33,63 @@ impl<const ALIGN: usize> AlignedMemory<{ ALIGN }> {
             p: ptr as *mut u64,
             sz_u64,
             layout,
+            locked: false,
+            secret: false,
         }
     }
 
+    // Allocates memory for secrets, which is zeroized when dropped
+    pub fn new_secret(sz_u64: usize) -> Self {
+        let mut out = Self::new(sz_u64);
+        out.secret = true;
+        out
+    }
+
+    // Like `new_secret`, but the memory is also locked into RAM, so it is never swapped to
+    // disk. The buffer covers whole pages, since unlocking a page would also unlock any other
+    // buffer on it.
+    #[cfg(unix)]
+    pub fn new_locked(sz_u64: usize) -> io::Result<Self> {
+        let page_sz = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let sz_bytes = usize::max(sz_u64 * size_of::<u64>(), 1);
+        let sz_pages = sz_bytes.div_ceil(page_sz) * page_sz;
+        let layout = Layout::from_size_align(sz_pages, usize::max(ALIGN, page_sz)).unwrap();
+
+        let ptr;
+        unsafe {
+            ptr = alloc_zeroed(layout);
+            if libc::mlock(ptr as *const libc::c_void, sz_pages) != 0 {
+                let err = io::Error::last_os_error();
+                dealloc(ptr, layout);
+                return Err(err);
+            }
+        }
+
+        Ok(Self {
+            p: ptr as *mut u64,
+            sz_u64,
+            layout,
+            locked: true,
+            secret: true,
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn new_locked(_sz_u64: usize) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "memory locking is not supported on this platform",
+        ))
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    pub fn is_secret(&self) -> bool {
+        self.secret
+    }
+
     // pub fn from(data: &[u8]) -> Self {
     //     let sz_u64 = (data.len() + size_of::<u64>() - 1) / size_of::<u64>();
     //     let mut out = Self::new(sz_u64);
@@ -69,7 +128,14 @@ unsafe impl<const ALIGN: usize> Sync for AlignedMemory<{ ALIGN }> {}
 
 impl<const ALIGN: usize> Drop for AlignedMemory<{ ALIGN }> {
     fn drop(&mut self) {
+        if self.secret {
+            self.as_mut_slice().zeroize();
+        }
         unsafe {
+            #[cfg(unix)]
+            if self.locked {
+                libc::munlock(self.p as *const libc::c_void, self.layout.size());
+            }
             dealloc(self.p as *mut u8, self.layout);
         }
     }
@@ -91,8 +157,42 @@ impl<const ALIGN: usize> IndexMut<usize> for AlignedMemory<{ ALIGN }> {
 
 impl<const ALIGN: usize> Clone for AlignedMemory<{ ALIGN }> {
     fn clone(&self) -> Self {
-        let mut out = Self::new(self.sz_u64);
+        // past the limit on locked memory, a copy is only zeroized on drop
+        let mut out = if self.locked {
+            Self::new_locked(self.sz_u64).unwrap_or_else(|_| Self::new_secret(self.sz_u64))
+        } else if self.secret {
+            Self::new_secret(self.sz_u64)
+        } else {
+            Self::new(self.sz_u64)
+        };
         out.as_mut_slice().copy_from_slice(self.as_slice());
         out
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn locked_memory_is_correct() {
+        let mut mem = AlignedMemory64::new_locked(1000).unwrap();
+        assert!(mem.is_locked());
+        assert_eq!(mem.len(), 1000);
+        assert!(mem.as_slice().iter().all(|&x| x == 0));
+        assert_eq!(unsafe { mem.as_ptr() } as usize % ALIGN_SIMD, 0);
+
+        mem[999] = 7;
+        let copy = mem.clone();
+        assert!(copy.is_locked() && copy.is_secret());
+        assert_eq!(copy[999], 7);
+
+        let mem = AlignedMemory64::new(1000);
+        assert!(!mem.is_locked() && !mem.is_secret());
+        assert!(!mem.clone().is_secret());
+        let mem = AlignedMemory64::new_secret(1000);
+        assert!(!mem.is_locked() && mem.is_secret());
+        assert!(mem.clone().is_secret());
+    }
+}
@@ -19,11 +19,19 @@ pub fn log2_ceil_usize(a: usize) -> usize {
 }
 
 pub fn multiply_modular(params: &Params, a: u64, b: u64, c: usize) -> u64 {
-    barrett_coeff_u64(params, a * b, c)
+    if params.moduli[c] < 1 << SMALL_MODULUS_BITS {
+        barrett_coeff_u64(params, a * b, c)
+    } else {
+        barrett_coeff_u128(params, (a as u128) * (b as u128), c)
+    }
 }
 
 pub fn multiply_add_modular(params: &Params, a: u64, b: u64, x: u64, c: usize) -> u64 {
-    barrett_coeff_u64(params, a * b + x, c)
+    if params.moduli[c] < 1 << SMALL_MODULUS_BITS {
+        barrett_coeff_u64(params, a * b + x, c)
+    } else {
+        barrett_coeff_u128(params, (a as u128) * (b as u128) + (x as u128), c)
+    }
 }
 
 pub fn add_modular(params: &Params, a: u64, b: u64, c: usize) -> u64 {
@@ -208,6 +216,13 @@ pub fn barrett_reduction_u128(params: &Params, val: u128) -> u64 {
     barrett_reduction_u128_raw(modulus, cr0, cr1, val)
 }
 
+pub fn barrett_coeff_u128(params: &Params, val: u128, n: usize) -> u64 {
+    let modulus = params.moduli[n];
+    let cr0 = params.barrett_cr_0[n];
+    let cr1 = params.barrett_cr_1[n];
+    barrett_reduction_u128_raw(modulus, cr0, cr1, val)
+}
+
 // Following code is ported from SEAL (github.com/microsoft/SEAL)
 
 pub fn get_significant_bit_count(val: &[u64]) -> usize {
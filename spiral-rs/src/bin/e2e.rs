@@ -3,10 +3,13 @@ use rand::Rng;
 use spiral_rs::arith::*;
 use spiral_rs::client::*;
 use spiral_rs::params::*;
+use spiral_rs::security::*;
 use spiral_rs::server::*;
 use spiral_rs::util::*;
 use std::env;
 use std::fs;
+use std::process;
+use std::sync::Arc;
 use std::time::Instant;
 
 fn print_params_summary(params: &Params) {
@@ -28,12 +31,27 @@ fn main() {
         let inp_params_fname = &args[1];
         let params_json_str = fs::read_to_string(inp_params_fname).unwrap();
 
-        params = params_from_json(&params_json_str);
+        params = Arc::new(
+            try_params_from_json_with_policy(&params_json_str, &SecurityPolicy::default())
+                .unwrap_or_else(|e| {
+                    eprintln!("{}: {}", inp_params_fname, e);
+                    process::exit(1);
+                }),
+        );
     } else {
         let target_num_log2: usize = args[1].parse().unwrap();
         let item_size_bytes: usize = args[2].parse().unwrap();
+        println!(
+            "Searching for parameters for 2^{} x {} bytes...",
+            target_num_log2, item_size_bytes
+        );
 
-        params = get_params_from_store(target_num_log2, item_size_bytes);
+        params = Arc::new(
+            find_params(target_num_log2, item_size_bytes).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                process::exit(1);
+            }),
+        );
     }
 
     print_params_summary(&params);
@@ -52,7 +70,7 @@ fn main() {
     let pub_params = client.generate_keys();
     let pub_params_buf = pub_params.serialize();
     println!("public parameters size: {} bytes", pub_params_buf.len());
-    let query = client.generate_query(idx_target);
+    let query = client.generate_query(idx_target).unwrap();
     let query_buf = query.serialize();
     println!("initial query size: {} bytes", query_buf.len());
 
@@ -66,7 +84,7 @@ fn main() {
     println!("response size: {} bytes", response.len());
 
     println!("decoding response");
-    let result = client.decode_response(response.as_slice());
+    let result = client.decode_response(response.as_slice()).unwrap();
 
     let p_bits = log2_ceil(params.pt_modulus) as usize;
     let corr_result = corr_item.to_vec(p_bits, params.modp_words_per_chunk());
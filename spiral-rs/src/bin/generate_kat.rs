@@ -0,0 +1,17 @@
+use spiral_rs::kat::*;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+// Writes one known-answer test per preset into the given directory (default: kat)
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let out_dir = Path::new(args.get(1).map(String::as_str).unwrap_or("kat"));
+    fs::create_dir_all(out_dir).unwrap();
+
+    for preset in kat_presets() {
+        let kat = generate_kat(&preset, KAT_SEED, 5).unwrap();
+        let path = write_kat(out_dir, &kat).unwrap();
+        println!("wrote {}", path.display());
+    }
+}
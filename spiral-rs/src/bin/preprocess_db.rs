@@ -1,7 +1,9 @@
 use std::env;
 use std::fs::File;
 use std::io::Write;
+use std::process;
 use std::slice::from_raw_parts;
+use std::sync::Arc;
 
 use spiral_rs::server::*;
 use spiral_rs::util::*;
@@ -16,11 +18,18 @@ fn main() {
     if args.len() > 2 {
         let target_num_log2: usize = args[3].parse().unwrap();
         let item_size_bytes: usize = args[4].parse().unwrap();
-
-        base_params = get_params_from_store(target_num_log2, item_size_bytes);
+        println!(
+            "Searching for parameters for 2^{} x {} bytes...",
+            target_num_log2, item_size_bytes
+        );
+
+        base_params = find_params(target_num_log2, item_size_bytes).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(1);
+        });
     }
     
-    let params = &base_params;
+    let params = &Arc::new(base_params);
 
     let db = load_db_from_seek(params, inp_db_path);
 
@@ -1,30 +1,59 @@
 use futures::StreamExt;
 use spiral_rs::aligned_memory::*;
 use spiral_rs::client::*;
+use spiral_rs::error::SpiralError;
 use spiral_rs::params::*;
 use spiral_rs::server::*;
+use spiral_rs::store::*;
 use spiral_rs::util::*;
-use std::collections::HashMap;
-use std::collections::VecDeque;
 use std::env;
 use std::fs::File;
-use std::sync::Mutex;
-use std::time::Instant;
+use std::num::NonZeroUsize;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
 use actix_cors::Cors;
 use actix_http::HttpServiceBuilder;
 use actix_server::Server;
 use actix_service::map_config;
-use actix_web::error::PayloadError;
-use actix_web::{get, http, middleware, post, web, App};
+use actix_web::error::{
+    ErrorBadRequest, ErrorInternalServerError, ErrorServiceUnavailable, PayloadError,
+};
+use actix_web::{get, http, middleware, post, rt, web, App};
 use serde::Deserialize;
-const PUB_PARAMS_MAX: usize = 250;
-
-struct ServerState<'a> {
+use tokio::sync::Semaphore;
+// How many queries are processed at once, unless SPIRAL_MAX_CONCURRENT_QUERIES says otherwise;
+// each one already runs on every core
+const DEFAULT_MAX_CONCURRENT_QUERIES: usize = 2;
+// How long a query waits for a turn before the server reports that it is busy
+const QUERY_QUEUE_TIMEOUT: Duration = Duration::from_secs(30);
+// Public parameters are kept in room for this many setups, unless
+// SPIRAL_PUB_PARAMS_BUDGET_MB sets the room directly
+const DEFAULT_PUB_PARAMS_ENTRIES: usize = 250;
+// How long a setup stays valid, unless SPIRAL_PUB_PARAMS_TTL_SECS says otherwise
+const DEFAULT_PUB_PARAMS_TTL_SECS: u64 = 24 * 60 * 60;
+// Setups are kept only in memory, unless SPIRAL_PUB_PARAMS_DIR names a directory to keep them
+// in across restarts
+const PUB_PARAMS_DIR_VAR: &str = "SPIRAL_PUB_PARAMS_DIR";
+// That directory holds files for this many setups, unless SPIRAL_PUB_PARAMS_DIR_BUDGET_MB sets
+// its size directly
+const DEFAULT_PUB_PARAMS_DIR_ENTRIES: usize = 1000;
+// Setups get random ids, unless SPIRAL_CONTENT_SETUP_IDS=true makes each id a hash of the
+// public parameters, so that uploading them again returns the same id
+const DEFAULT_CONTENT_SETUP_IDS: bool = false;
+
+struct ServerState {
     fname: String,
-    params: &'a Params,
-    db: AlignedMemory64,
-    pub_params_map: Mutex<(VecDeque<String>, HashMap<String, PublicParameters<'a>>)>,
+    params: Arc<Params>,
+    // Queries clone the current snapshot and keep it until they finish, so a reload can swap in
+    // a new one without disturbing them
+    db: RwLock<Arc<AlignedMemory64>>,
+    reloading: AtomicBool,
+    pub_params_store: Box<dyn PublicParamsStore>,
+    content_setup_ids: bool,
+    query_permits: Semaphore,
 }
 
 async fn get_request_bytes(
@@ -43,45 +72,90 @@ async fn get_request_bytes(
     Ok(bytes.to_vec())
 }
 
-fn get_other_io_err() -> PayloadError {
-    PayloadError::Io(std::io::Error::from(std::io::ErrorKind::Other))
-}
-
-fn other_io_err<T>(_: T) -> PayloadError {
-    get_other_io_err()
-}
-
 fn get_not_found_err() -> PayloadError {
     PayloadError::Io(std::io::Error::from(std::io::ErrorKind::NotFound))
 }
 
+fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    match env::var(name) {
+        Ok(s) => s
+            .parse()
+            .unwrap_or_else(|_| panic!("{} has an invalid value {:?}", name, s)),
+        Err(_) => default,
+    }
+}
+
 #[get("/debug")]
-async fn debug<'a>() -> Result<String, http::Error> {
+async fn debug() -> Result<String, http::Error> {
     Ok("{{\"status\":\"debugging\"}}".to_string())
 }
 
+fn current_db(data: &ServerState) -> Result<Arc<AlignedMemory64>, actix_web::Error> {
+    Ok(data
+        .db
+        .read()
+        .map_err(|_| ErrorInternalServerError("lock poisoned"))?
+        .clone())
+}
+
+// Clears the reload flag once the reload that set it is over, however it ends
+struct ReloadGuard(web::Data<ServerState>);
+
+impl Drop for ReloadGuard {
+    fn drop(&mut self) {
+        self.0.reloading.store(false, Ordering::Release);
+    }
+}
+
+// Loads the database again into a second buffer, so that memory for two copies is needed
+// while it runs, then swaps it in. The work runs on the blocking pool and finishes even if the
+// client goes away, so the flag is only cleared when it does.
 #[post("/reload")]
-async fn reload<'a>(
-    data: web::Data<ServerState<'a>>,
-) -> Result<String, http::Error> {
-    let db_len = data.db.len();
-    let db_data = unsafe {
-        let db_data_ptr = data.db.as_ptr() as *mut u64;
-        std::slice::from_raw_parts_mut(db_data_ptr, db_len)
-    };
+async fn reload(data: web::Data<ServerState>) -> Result<String, actix_web::Error> {
+    if data.reloading.swap(true, Ordering::AcqRel) {
+        return Err(ErrorServiceUnavailable("a reload is already in progress"));
+    }
+    let guard = ReloadGuard(data.clone());
+    let loading_time = web::block(move || {
+        let state = &guard.0;
+        let now = Instant::now();
+        let mut file = File::open(&state.fname)?;
+        let db = load_preprocessed_db_from_file(&state.params, &mut file)?;
+        let loading_time = now.elapsed();
+
+        // queries still running keep their own reference to the old database
+        let old_db = std::mem::replace(
+            &mut *state
+                .db
+                .write()
+                .map_err(|_| std::io::Error::other("lock poisoned"))?,
+            Arc::new(db),
+        );
+        drop(old_db);
+        Ok::<_, std::io::Error>(loading_time)
+    })
+    .await?
+    .map_err(ErrorInternalServerError)?;
 
-    let mut file = File::open(data.fname.clone()).unwrap();
-    let now = Instant::now();
-    load_file_unsafe(db_data, &mut file);
     Ok(format!(
-        "{{\"status\":\"done reloading\", \"loading_time_ms\":{}}}",
-        now.elapsed().as_millis()
+        "{{\"status\":\"done reloading\", \"loading_time_ms\":{}, \"swapped\":true}}",
+        loading_time.as_millis()
     ))
 }
 
 #[get("/")]
-async fn index<'a>(data: web::Data<ServerState<'a>>) -> String {
-    format!("Hello {} {}!", data.params.poly_len, data.db.as_slice()[5])
+async fn index(data: web::Data<ServerState>) -> Result<String, actix_web::Error> {
+    let db = current_db(&data)?;
+    Ok(format!(
+        "Hello {} {}!",
+        data.params.poly_len,
+        db.as_slice()[5]
+    ))
+}
+
+#[get("/params")]
+async fn params_json(data: web::Data<ServerState>) -> String {
+    data.params.to_json()
 }
 
 #[derive(Deserialize)]
@@ -90,117 +164,155 @@ pub struct CheckUuid {
 }
 
 #[get("/check")]
-async fn check<'a>(
+async fn check(
     web::Query(query_params): web::Query<CheckUuid>,
-    data: web::Data<ServerState<'a>>,
+    data: web::Data<ServerState>,
 ) -> Result<String, http::Error> {
-    let pub_params_map = data.pub_params_map.lock().map_err(other_io_err)?;
-    let has_uuid = pub_params_map.1.contains_key(&query_params.uuid);
+    let remaining = data.pub_params_store.remaining_lifetime(&query_params.uuid);
     Ok(format!(
-        "{{\"uuid\":\"{}\", \"is_valid\":{}}}",
-        query_params.uuid, has_uuid
+        "{{\"uuid\":\"{}\", \"is_valid\":{}, \"remaining_secs\":{}}}",
+        query_params.uuid,
+        remaining.is_some(),
+        remaining.unwrap_or_default().as_secs()
     ))
 }
 
 #[post("/setup")]
-async fn setup<'a>(
-    body: web::Bytes,
-    data: web::Data<ServerState<'a>>,
-) -> Result<String, http::Error> {
+async fn setup(body: web::Bytes, data: web::Data<ServerState>) -> Result<String, actix_web::Error> {
     // Parse the request
-    let pub_params = PublicParameters::deserialize(data.params, &body);
-
-    // Generate a UUID and store it
-    let uuid = uuid::Uuid::new_v4();
-    let mut pub_params_map = data.pub_params_map.lock().map_err(other_io_err)?;
-    pub_params_map.0.push_back(uuid.to_string());
-    pub_params_map.1.insert(uuid.to_string(), pub_params);
-
-    // If too many public parameters, remove by LRU
-    if pub_params_map.1.len() > PUB_PARAMS_MAX {
-        let lru_uuid_str = pub_params_map.0.pop_front().ok_or(get_other_io_err())?;
-        pub_params_map.1.remove(&lru_uuid_str);
-    }
+    let pub_params = PublicParameters::deserialize(&data.params, &body).map_err(ErrorBadRequest)?;
 
-    Ok(format!("{{\"id\":\"{}\"}}", uuid.to_string()))
-}
+    // Pick an id and store the parameters under it; uploading them again under a content id
+    // starts their lifetime over
+    let id = if data.content_setup_ids {
+        setup_id(&body)
+    } else {
+        uuid::Uuid::new_v4().to_string()
+    };
+    let state = data.clone();
+    let stored_id = id.clone();
+    web::block(move || state.pub_params_store.insert(&stored_id, pub_params))
+        .await?
+        .map_err(ErrorInternalServerError)?;
 
-const UUID_V4_STR_BYTES: usize = 36;
+    Ok(format!("{{\"id\":\"{}\"}}", id))
+}
 
 #[post("/query")]
-async fn query<'a>(
+async fn query(
     body: web::Payload,
-    data: web::Data<ServerState<'a>>,
-) -> Result<Vec<u8>, http::Error> {
+    data: web::Data<ServerState>,
+) -> Result<Vec<u8>, actix_web::Error> {
     // Parse the UUID
     let request_bytes =
-        get_request_bytes(body, UUID_V4_STR_BYTES + data.params.query_bytes()).await?;
-    let uuid_bytes = &request_bytes.as_slice()[..UUID_V4_STR_BYTES];
-    let data_bytes = &request_bytes.as_slice()[UUID_V4_STR_BYTES..];
+        get_request_bytes(body, SETUP_ID_LENGTH + data.params.query_bytes()).await?;
+    if request_bytes.len() < SETUP_ID_LENGTH {
+        return Err(ErrorBadRequest(SpiralError::Truncated {
+            needed: SETUP_ID_LENGTH,
+            available: request_bytes.len(),
+        }));
+    }
+    let (uuid_bytes, data_bytes) = request_bytes.split_at(SETUP_ID_LENGTH);
     let uuid =
         uuid::Uuid::try_parse_ascii(uuid_bytes).map_err(|_| PayloadError::EncodingCorrupted)?;
 
-    // Look up UUID and get public parameters
-    let pub_params_map = data.pub_params_map.lock().map_err(other_io_err)?;
-    let pub_params = pub_params_map
-        .1
-        .get(&uuid.to_string())
+    // Look up UUID and get public parameters, which may have to be read from disk
+    let state = data.clone();
+    let pub_params = web::block(move || state.pub_params_store.get(&uuid.to_string()))
+        .await?
         .ok_or(get_not_found_err())?;
 
     // Parse the query
-    let query = Query::deserialize(data.params, data_bytes);
+    let query = Query::deserialize(&data.params, data_bytes).map_err(ErrorBadRequest)?;
 
-    // Process the query
-    let result = process_query(data.params, pub_params, &query, data.db.as_slice());
+    // Wait for a turn, then process the query off the async workers
+    let _permit = rt::time::timeout(QUERY_QUEUE_TIMEOUT, data.query_permits.acquire())
+        .await
+        .map_err(|_| ErrorServiceUnavailable("too many queries in progress"))?
+        .map_err(ErrorInternalServerError)?;
+    let state = data.clone();
+    let db = current_db(&data)?;
+    let result =
+        web::block(move || process_query(&state.params, &pub_params, &query, db.as_slice()))
+            .await?;
 
     Ok(result)
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let cfg_expand = r#"
-        {"n": 2,
-        "nu_1": 10,
-        "nu_2": 6,
-        "p": 512,
-        "q2_bits": 21,
-        "s_e": 85.83255142749422,
-        "t_gsw": 10,
-        "t_conv": 4,
-        "t_exp_left": 16,
-        "t_exp_right": 56,
-        "instances": 11,
-        "db_item_size": 100000 }
-    "#;
-
     let args: Vec<String> = env::args().collect();
     let db_preprocessed_path = &args[1];
     let mut port = "8088";
-    let box_params;
+    let params;
     if args.len() > 2 {
         port = &args[2];
         let target_num_log2: usize = args[3].parse().unwrap();
         let item_size_bytes: usize = args[4].parse().unwrap();
-
-        box_params = Box::new(get_params_from_store(target_num_log2, item_size_bytes));
+        println!(
+            "Searching for parameters for 2^{} x {} bytes...",
+            target_num_log2, item_size_bytes
+        );
+
+        params = Arc::new(
+            find_params(target_num_log2, item_size_bytes)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?,
+        );
     } else {
-        box_params = Box::new(params_from_json(cfg_expand));
+        params = Arc::new(params_from_json(&CFG_16_100000.replace("'", "\"")));
     }
 
     // FIXME: very hacky to do port math
     let debug_port = (port.parse::<u64>().unwrap() + 1000).to_string();
-    
-    let params: &'static Params = Box::leak(box_params);
+
+    // with no permits every query would wait out its timeout, so zero is rejected
+    let max_concurrent_queries = env_or(
+        "SPIRAL_MAX_CONCURRENT_QUERIES",
+        NonZeroUsize::new(DEFAULT_MAX_CONCURRENT_QUERIES).unwrap(),
+    );
+    let pub_params_budget = match env::var("SPIRAL_PUB_PARAMS_BUDGET_MB") {
+        Ok(_) => env_or("SPIRAL_PUB_PARAMS_BUDGET_MB", 0usize) << 20,
+        Err(_) => DEFAULT_PUB_PARAMS_ENTRIES * params.setup_bytes(),
+    };
+    let pub_params_ttl = Duration::from_secs(env_or(
+        "SPIRAL_PUB_PARAMS_TTL_SECS",
+        DEFAULT_PUB_PARAMS_TTL_SECS,
+    ));
+
+    let content_setup_ids = env_or("SPIRAL_CONTENT_SETUP_IDS", DEFAULT_CONTENT_SETUP_IDS);
+    let pub_params_store: Box<dyn PublicParamsStore> = match env::var(PUB_PARAMS_DIR_VAR) {
+        Ok(dir) => {
+            let dir_budget = match env::var("SPIRAL_PUB_PARAMS_DIR_BUDGET_MB") {
+                Ok(_) => env_or("SPIRAL_PUB_PARAMS_DIR_BUDGET_MB", 0usize) << 20,
+                Err(_) => DEFAULT_PUB_PARAMS_DIR_ENTRIES * params.setup_bytes(),
+            };
+            Box::new(FilePublicParamsStore::open(
+                dir,
+                &params,
+                pub_params_budget,
+                dir_budget,
+                pub_params_ttl,
+            )?)
+        }
+        Err(_) => Box::new(MemoryPublicParamsStore::new(
+            &params,
+            pub_params_budget,
+            pub_params_ttl,
+        )),
+    };
 
     let mut file = File::open(db_preprocessed_path).unwrap();
-    let db = load_preprocessed_db_from_file(params, &mut file);
+    let db = load_preprocessed_db_from_file(&params, &mut file)?;
     println!("Done loading from DB.");
 
     let server_state = ServerState {
         fname: db_preprocessed_path.clone(),
         params: params,
-        db: db,
-        pub_params_map: Mutex::new((VecDeque::new(), HashMap::new())),
+        db: RwLock::new(Arc::new(db)),
+        reloading: AtomicBool::new(false),
+        pub_params_store,
+        content_setup_ids,
+        query_permits: Semaphore::new(max_concurrent_queries.get()),
     };
     let state = web::Data::new(server_state);
     let state_dup = state.clone();
@@ -226,6 +338,7 @@ async fn main() -> std::io::Result<()> {
             .service(setup)
             .service(query)
             .service(check)
+            .service(params_json)
     };
 
     let app_builder_util = move || {
@@ -255,3 +368,57 @@ async fn main() -> std::io::Result<()> {
         .run()
         .await
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use actix_web::test;
+
+    fn get_state() -> web::Data<ServerState> {
+        let params = Arc::new(get_test_params());
+        web::Data::new(ServerState {
+            fname: String::new(),
+            params: params.clone(),
+            db: RwLock::new(Arc::new(AlignedMemory64::new(0))),
+            reloading: AtomicBool::new(false),
+            pub_params_store: Box::new(MemoryPublicParamsStore::new(
+                &params,
+                params.setup_bytes(),
+                Duration::from_secs(60),
+            )),
+            content_setup_ids: false,
+            query_permits: Semaphore::new(1),
+        })
+    }
+
+    #[actix_web::test]
+    async fn reload_clears_its_flag() {
+        let state = get_state();
+        let app = test::init_service(App::new().app_data(state.clone()).service(reload)).await;
+        for _ in 0..2 {
+            // there is no database file, so each reload fails, but neither is left in progress
+            let req = test::TestRequest::post().uri("/reload").to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::INTERNAL_SERVER_ERROR);
+            assert!(!state.reloading.load(Ordering::Acquire));
+        }
+
+        state.reloading.store(true, Ordering::Release);
+        let req = test::TestRequest::post().uri("/reload").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[actix_web::test]
+    async fn short_queries_are_rejected() {
+        let app = test::init_service(App::new().app_data(get_state()).service(query)).await;
+        for body in [&b""[..], b"0f3c2a9e-5b1d"] {
+            let req = test::TestRequest::post()
+                .uri("/query")
+                .set_payload(body)
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST);
+        }
+    }
+}
@@ -0,0 +1,299 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, Instant},
+};
+
+struct CacheEntry<V> {
+    value: V,
+    size_bytes: usize,
+    expires_at: Instant,
+    last_used: u64,
+}
+
+// A cache that holds entries for at most `ttl` after they are inserted, and evicts the least
+// recently used ones once their total size passes `max_bytes`. Expired entries are dropped as
+// they are found, and whenever an insertion needs room.
+pub struct LruTtlCache<V> {
+    entries: HashMap<String, CacheEntry<V>>,
+    // last use -> key, oldest first
+    by_use: BTreeMap<u64, String>,
+    uses: u64,
+    total_bytes: usize,
+    max_bytes: usize,
+    ttl: Duration,
+}
+
+impl<V: Clone> LruTtlCache<V> {
+    pub fn new(max_bytes: usize, ttl: Duration) -> Self {
+        Self {
+            entries: HashMap::new(),
+            by_use: BTreeMap::new(),
+            uses: 0,
+            total_bytes: 0,
+            max_bytes,
+            ttl,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn total_bytes(&self) -> usize {
+        self.total_bytes
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    // Inserts `value`, replacing any entry under `key`. Returns false, and leaves the cache as
+    // it was, if the value alone is over the budget.
+    pub fn insert(&mut self, key: String, value: V, size_bytes: usize) -> bool {
+        self.insert_at(key, value, size_bytes, self.ttl, Instant::now())
+    }
+
+    // Like `insert`, for an entry that expires after `ttl` rather than the cache's own
+    pub fn insert_with_ttl(
+        &mut self,
+        key: String,
+        value: V,
+        size_bytes: usize,
+        ttl: Duration,
+    ) -> bool {
+        self.insert_at(key, value, size_bytes, ttl, Instant::now())
+    }
+
+    // Like `insert_with_ttl`, but returns the keys of the entries dropped to make room,
+    // expired ones included, or None if the value alone is over the budget
+    pub fn insert_evicting(
+        &mut self,
+        key: String,
+        value: V,
+        size_bytes: usize,
+        ttl: Duration,
+    ) -> Option<Vec<String>> {
+        self.insert_evicting_at(key, value, size_bytes, ttl, Instant::now())
+    }
+
+    // Returns the value under `key` and marks it as just used
+    pub fn get(&mut self, key: &str) -> Option<V> {
+        self.get_at(key, Instant::now())
+    }
+
+    // How long the entry under `key` has left; using an entry does not extend it
+    pub fn remaining_lifetime(&mut self, key: &str) -> Option<Duration> {
+        self.remaining_lifetime_at(key, Instant::now())
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<V> {
+        let entry = self.entries.remove(key)?;
+        self.by_use.remove(&entry.last_used);
+        self.total_bytes -= entry.size_bytes;
+        Some(entry.value)
+    }
+
+    fn insert_at(
+        &mut self,
+        key: String,
+        value: V,
+        size_bytes: usize,
+        ttl: Duration,
+        now: Instant,
+    ) -> bool {
+        self.insert_evicting_at(key, value, size_bytes, ttl, now)
+            .is_some()
+    }
+
+    fn insert_evicting_at(
+        &mut self,
+        key: String,
+        value: V,
+        size_bytes: usize,
+        ttl: Duration,
+        now: Instant,
+    ) -> Option<Vec<String>> {
+        if size_bytes > self.max_bytes {
+            return None;
+        }
+        self.remove(&key);
+
+        let mut evicted = self.remove_expired(now);
+        while self.total_bytes + size_bytes > self.max_bytes {
+            let (_, lru_key) = self.by_use.pop_first().unwrap();
+            let entry = self.entries.remove(&lru_key).unwrap();
+            self.total_bytes -= entry.size_bytes;
+            evicted.push(lru_key);
+        }
+
+        let last_used = self.next_use();
+        self.by_use.insert(last_used, key.clone());
+        self.entries.insert(
+            key,
+            CacheEntry {
+                value,
+                size_bytes,
+                expires_at: now + ttl,
+                last_used,
+            },
+        );
+        self.total_bytes += size_bytes;
+        Some(evicted)
+    }
+
+    fn get_at(&mut self, key: &str, now: Instant) -> Option<V> {
+        self.remaining_lifetime_at(key, now)?;
+        let last_used = self.next_use();
+        let entry = self.entries.get_mut(key).unwrap();
+        let key = self.by_use.remove(&entry.last_used).unwrap();
+        self.by_use.insert(last_used, key);
+        entry.last_used = last_used;
+        Some(entry.value.clone())
+    }
+
+    fn remaining_lifetime_at(&mut self, key: &str, now: Instant) -> Option<Duration> {
+        let expires_at = self.entries.get(key)?.expires_at;
+        if expires_at <= now {
+            self.remove(key);
+            return None;
+        }
+        Some(expires_at - now)
+    }
+
+    fn remove_expired(&mut self, now: Instant) -> Vec<String> {
+        let expired: Vec<String> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.expires_at <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &expired {
+            self.remove(key);
+        }
+        expired
+    }
+
+    fn next_use(&mut self) -> u64 {
+        self.uses += 1;
+        self.uses
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn cache_evicts_least_recently_used() {
+        let now = Instant::now();
+        let mut cache = LruTtlCache::new(300, TTL);
+        assert!(cache.insert_at("a".to_string(), 1, 100, TTL, now));
+        assert!(cache.insert_at("b".to_string(), 2, 100, TTL, now));
+        assert!(cache.insert_at("c".to_string(), 3, 100, TTL, now));
+        assert_eq!(cache.total_bytes(), 300);
+
+        // "a" was inserted first, but "b" is the one left unused
+        assert_eq!(cache.get_at("a", now), Some(1));
+        assert!(cache.insert_at("d".to_string(), 4, 100, TTL, now));
+        assert_eq!(cache.get_at("b", now), None);
+        assert_eq!(cache.get_at("a", now), Some(1));
+        assert_eq!(cache.len(), 3);
+
+        // a larger entry evicts as many as it needs
+        assert!(cache.insert_at("e".to_string(), 5, 200, TTL, now));
+        assert_eq!(cache.get_at("c", now), None);
+        assert_eq!(cache.get_at("d", now), None);
+        assert_eq!(cache.get_at("a", now), Some(1));
+        assert_eq!(cache.total_bytes(), 300);
+
+        assert!(!cache.insert_at("f".to_string(), 6, 301, TTL, now));
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn cache_expires_entries() {
+        let now = Instant::now();
+        let mut cache = LruTtlCache::new(300, TTL);
+        assert!(cache.insert_at("a".to_string(), 1, 100, TTL, now));
+        let later = now + Duration::from_secs(45);
+        assert!(cache.insert_at("b".to_string(), 2, 100, TTL, later));
+
+        // using an entry does not extend its lifetime
+        assert_eq!(cache.get_at("a", later), Some(1));
+        assert_eq!(
+            cache.remaining_lifetime_at("a", later),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(cache.remaining_lifetime_at("b", later), Some(TTL));
+
+        let expired = now + TTL;
+        assert_eq!(cache.get_at("a", expired), None);
+        assert_eq!(cache.remaining_lifetime_at("a", expired), None);
+        assert_eq!(cache.get_at("b", expired), Some(2));
+        assert_eq!(cache.total_bytes(), 100);
+
+        // expired entries make room before live ones are evicted
+        assert!(cache.insert_at("c".to_string(), 3, 100, TTL, now));
+        assert!(cache.insert_at("d".to_string(), 4, 200, TTL, expired));
+        assert_eq!(cache.get_at("b", expired), Some(2));
+        assert_eq!(cache.get_at("c", expired), None);
+    }
+
+    #[test]
+    fn cache_reports_evictions() {
+        let now = Instant::now();
+        let mut cache = LruTtlCache::new(300, TTL);
+        let short = Duration::from_secs(5);
+        assert!(cache.insert_at("a".to_string(), 1, 100, short, now));
+        assert!(cache.insert_at("b".to_string(), 2, 100, TTL, now));
+        assert!(cache.insert_at("c".to_string(), 3, 100, TTL, now));
+
+        let mut evicted = cache
+            .insert_evicting_at("d".to_string(), 4, 200, TTL, now + short)
+            .unwrap();
+        evicted.sort();
+        assert_eq!(evicted, vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(
+            cache.insert_evicting_at("d".to_string(), 5, 100, TTL, now + short),
+            Some(vec![])
+        );
+        assert_eq!(
+            cache.insert_evicting_at("e".to_string(), 6, 301, TTL, now),
+            None
+        );
+    }
+
+    #[test]
+    fn cache_keeps_entry_ttl() {
+        let now = Instant::now();
+        let mut cache = LruTtlCache::new(300, TTL);
+        let short = Duration::from_secs(5);
+        assert!(cache.insert_at("a".to_string(), 1, 100, short, now));
+        assert_eq!(cache.remaining_lifetime_at("a", now), Some(short));
+        assert_eq!(cache.get_at("a", now + short), None);
+    }
+
+    #[test]
+    fn cache_replaces_entries() {
+        let now = Instant::now();
+        let mut cache = LruTtlCache::new(300, TTL);
+        assert!(cache.insert_at("a".to_string(), 1, 100, TTL, now));
+        assert!(cache.insert_at("a".to_string(), 2, 250, TTL, now));
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.total_bytes(), 250);
+
+        // a replacement over the budget keeps the old entry
+        assert!(!cache.insert_at("a".to_string(), 3, 301, TTL, now));
+        assert_eq!(cache.get_at("a", now), Some(2));
+        assert_eq!(cache.total_bytes(), 250);
+        assert_eq!(cache.remove("a"), Some(2));
+        assert!(cache.is_empty());
+        assert_eq!(cache.total_bytes(), 0);
+    }
+}
@@ -1,22 +1,118 @@
 use crate::{
-    arith::*, discrete_gaussian::*, gadget::*, number_theory::*, params::*, poly::*, util::*,
+    aligned_memory::*, arith::*, discrete_gaussian::*, error::*, gadget::*, number_theory::*,
+    params::*, poly::*, util::*,
 };
-use rand::{Rng, SeedableRng};
+use rand::{CryptoRng, Rng, RngCore, SeedableRng};
 use rand_chacha::ChaCha20Rng;
-use std::{iter::once, mem::size_of};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::VecDeque,
+    io,
+    iter::once,
+    mem::size_of,
+    ptr,
+    sync::{
+        atomic::{compiler_fence, Ordering},
+        Arc,
+    },
+};
+use zeroize::Zeroize;
 
 pub type Seed = <ChaCha20Rng as SeedableRng>::Seed;
 pub const SEED_LENGTH: usize = 32;
+pub const SECRET_KEY_VERSION: u8 = 1;
+// The fraction of the rounding threshold beyond which a decoded response is flagged
+pub const NOISE_SUSPECT_RATIO: f64 = 0.5;
+pub const SETUP_ID_LENGTH: usize = 36;
+
+pub fn check_fingerprint(params: &Params, data: &[u8]) -> Result<(), SpiralError> {
+    let expected = params.fingerprint();
+    let found = u64::from_le_bytes(take_bytes(data, FINGERPRINT_LENGTH)?.try_into().unwrap());
+    if found == expected {
+        Ok(())
+    } else {
+        Err(SpiralError::WrongParams { expected, found })
+    }
+}
 
-fn new_vec_raw<'a>(
-    params: &'a Params,
-    num: usize,
-    rows: usize,
-    cols: usize,
-) -> Vec<PolyMatrixRaw<'a>> {
+// The id of serialized public parameters when a server addresses them by content: the first
+// 16 bytes of their SHA-256 digest, marked as a version 8 UUID so that it fits wherever a
+// random setup id does
+pub fn setup_id(pub_params: &[u8]) -> String {
+    let mut id: [u8; 16] = Sha256::digest(pub_params)[..16].try_into().unwrap();
+    id[6] = (id[6] & 0x0f) | 0x80;
+    id[8] = (id[8] & 0x3f) | 0x80;
+
+    let mut out = String::with_capacity(SETUP_ID_LENGTH);
+    for (i, byte) in id.iter().enumerate() {
+        if [4, 6, 8, 10].contains(&i) {
+            out.push('-');
+        }
+        out.push_str(&format!("{:02x}", byte));
+    }
+    out
+}
+
+// Checks the fingerprint and total length of serialized data, and strips the fingerprint
+fn deserialize_header<'b>(
+    params: &Params,
+    data: &'b [u8],
+    expected_len: usize,
+) -> Result<&'b [u8], SpiralError> {
+    check_fingerprint(params, data)?;
+    if data.len() != expected_len {
+        return Err(SpiralError::LengthMismatch {
+            expected: expected_len,
+            found: data.len(),
+        });
+    }
+    Ok(&data[FINGERPRINT_LENGTH..])
+}
+
+fn take_bytes(data: &[u8], needed: usize) -> Result<&[u8], SpiralError> {
+    data.get(..needed).ok_or(SpiralError::Truncated {
+        needed,
+        available: data.len(),
+    })
+}
+
+fn check_coeff(value: u64, modulus: u64) -> Result<u64, SpiralError> {
+    if value < modulus {
+        Ok(value)
+    } else {
+        Err(SpiralError::CoefficientOutOfRange { value, modulus })
+    }
+}
+
+// Checks that every residue packed into `val` by `pack_residues` is reduced
+fn check_packed_residues(params: &Params, val: u64) -> Result<u64, SpiralError> {
+    let bits = packed_residue_bits(params) * params.crt_count;
+    if bits < 64 && val >> bits != 0 {
+        return Err(SpiralError::CoefficientOutOfRange {
+            value: val,
+            modulus: params.modulus,
+        });
+    }
+    for n in 0..params.crt_count {
+        check_coeff(unpack_residue(params, val, n), params.moduli[n])?;
+    }
+    Ok(val)
+}
+
+fn check_index(params: &Params, idx_target: usize) -> Result<(), SpiralError> {
+    if idx_target >= params.num_items() {
+        return Err(SpiralError::IndexOutOfRange {
+            index: idx_target,
+            num_items: params.num_items(),
+        });
+    }
+    Ok(())
+}
+
+fn new_vec_raw(params: &Arc<Params>, num: usize, rows: usize, cols: usize) -> Vec<PolyMatrixRaw> {
     let mut v = Vec::with_capacity(num);
     for _ in 0..num {
-        v.push(PolyMatrixRaw::zero(params, rows, cols));
+        v.push(PolyMatrixRaw::zero(&params, rows, cols));
     }
     v
 }
@@ -42,31 +138,37 @@ fn serialize_vec_polymatrix_for_rng(vec: &mut Vec<u8>, a: &Vec<PolyMatrixRaw>) {
     }
 }
 
-fn deserialize_polymatrix_rng(a: &mut PolyMatrixRaw, data: &[u8], rng: &mut ChaCha20Rng) -> usize {
+fn deserialize_polymatrix_rng(
+    a: &mut PolyMatrixRaw,
+    data: &[u8],
+    rng: &mut ChaCha20Rng,
+) -> Result<usize, SpiralError> {
+    let sz = mat_sz_bytes_excl_first_row(a);
+    let data = take_bytes(data, sz)?;
+    let modulus = a.params.modulus;
     let (first_row, rest) = a
         .data
         .as_mut_slice()
         .split_at_mut(a.cols * a.params.poly_len);
     for i in 0..first_row.len() {
-        first_row[i] = get_inv_from_rng(a.params, rng);
+        first_row[i] = get_inv_from_rng(&a.params, rng);
     }
     for (i, chunk) in data.chunks(size_of::<u64>()).enumerate() {
-        rest[i] = u64::from_ne_bytes(chunk.try_into().unwrap());
+        rest[i] = check_coeff(u64::from_ne_bytes(chunk.try_into().unwrap()), modulus)?;
     }
-    mat_sz_bytes_excl_first_row(a)
+    Ok(sz)
 }
 
 fn deserialize_vec_polymatrix_rng(
     a: &mut Vec<PolyMatrixRaw>,
     data: &[u8],
     rng: &mut ChaCha20Rng,
-) -> usize {
-    let mut chunks = data.chunks(mat_sz_bytes_excl_first_row(&a[0]));
+) -> Result<usize, SpiralError> {
     let mut bytes_read = 0;
     for i in 0..a.len() {
-        bytes_read += deserialize_polymatrix_rng(&mut a[i], chunks.next().unwrap(), rng);
+        bytes_read += deserialize_polymatrix_rng(&mut a[i], &data[bytes_read..], rng)?;
     }
-    bytes_read
+    Ok(bytes_read)
 }
 
 fn extract_excl_rng_data(v_buf: &[u64]) -> Vec<u64> {
@@ -79,12 +181,12 @@ fn extract_excl_rng_data(v_buf: &[u64]) -> Vec<u64> {
     out
 }
 
-fn interleave_rng_data(params: &Params, v_buf: &[u64], rng: &mut ChaCha20Rng) -> Vec<u64> {
+fn interleave_rng_data(params: &Arc<Params>, v_buf: &[u64], rng: &mut ChaCha20Rng) -> Vec<u64> {
     let mut out = Vec::new();
 
     let mut reg_cts = Vec::new();
     for _ in 0..params.num_expanded() {
-        let mut sigma = PolyMatrixRaw::zero(&params, 2, 1);
+        let mut sigma = PolyMatrixRaw::zero(params, 2, 1);
         for z in 0..params.poly_len {
             sigma.data[z] = get_inv_from_rng(params, rng);
         }
@@ -104,16 +206,17 @@ fn interleave_rng_data(params: &Params, v_buf: &[u64], rng: &mut ChaCha20Rng) ->
     out
 }
 
-pub struct PublicParameters<'a> {
-    pub v_packing: Vec<PolyMatrixNTT<'a>>, // Ws
-    pub v_expansion_left: Option<Vec<PolyMatrixNTT<'a>>>,
-    pub v_expansion_right: Option<Vec<PolyMatrixNTT<'a>>>,
-    pub v_conversion: Option<Vec<PolyMatrixNTT<'a>>>, // V
+pub struct PublicParameters {
+    pub v_packing: Vec<PolyMatrixNTT>, // Ws
+    pub v_expansion_left: Option<Vec<PolyMatrixNTT>>,
+    pub v_expansion_right: Option<Vec<PolyMatrixNTT>>,
+    pub v_conversion: Option<Vec<PolyMatrixNTT>>, // V
     pub seed: Option<Seed>,
+    pub fingerprint: u64,
 }
 
-impl<'a> PublicParameters<'a> {
-    pub fn init(params: &'a Params) -> Self {
+impl PublicParameters {
+    pub fn init(params: &Arc<Params>) -> Self {
         if params.expand_queries {
             PublicParameters {
                 v_packing: Vec::new(),
@@ -121,6 +224,7 @@ impl<'a> PublicParameters<'a> {
                 v_expansion_right: Some(Vec::new()),
                 v_conversion: Some(Vec::new()),
                 seed: None,
+                fingerprint: params.fingerprint(),
             }
         } else {
             PublicParameters {
@@ -129,21 +233,20 @@ impl<'a> PublicParameters<'a> {
                 v_expansion_right: None,
                 v_conversion: None,
                 seed: None,
+                fingerprint: params.fingerprint(),
             }
         }
     }
 
-    fn from_ntt_alloc_vec(v: &Vec<PolyMatrixNTT<'a>>) -> Option<Vec<PolyMatrixRaw<'a>>> {
+    fn from_ntt_alloc_vec(v: &Vec<PolyMatrixNTT>) -> Option<Vec<PolyMatrixRaw>> {
         Some(v.iter().map(from_ntt_alloc).collect())
     }
 
-    fn from_ntt_alloc_opt_vec(
-        v: &Option<Vec<PolyMatrixNTT<'a>>>,
-    ) -> Option<Vec<PolyMatrixRaw<'a>>> {
+    fn from_ntt_alloc_opt_vec(v: &Option<Vec<PolyMatrixNTT>>) -> Option<Vec<PolyMatrixRaw>> {
         Some(v.as_ref()?.iter().map(from_ntt_alloc).collect())
     }
 
-    fn to_ntt_alloc_vec(v: &Vec<PolyMatrixRaw<'a>>) -> Option<Vec<PolyMatrixNTT<'a>>> {
+    fn to_ntt_alloc_vec(v: &Vec<PolyMatrixRaw>) -> Option<Vec<PolyMatrixNTT>> {
         Some(v.iter().map(to_ntt_alloc).collect())
     }
 
@@ -158,6 +261,7 @@ impl<'a> PublicParameters<'a> {
 
     pub fn serialize(&self) -> Vec<u8> {
         let mut data = Vec::new();
+        data.extend(self.fingerprint.to_le_bytes());
         if self.seed.is_some() {
             let seed = self.seed.as_ref().unwrap();
             data.extend(seed);
@@ -170,67 +274,76 @@ impl<'a> PublicParameters<'a> {
         data
     }
 
-    pub fn deserialize(params: &'a Params, data: &[u8]) -> Self {
-        assert_eq!(params.setup_bytes(), data.len());
+    pub fn setup_id(&self) -> String {
+        setup_id(&self.serialize())
+    }
+
+    pub fn deserialize(params: &Arc<Params>, data: &[u8]) -> Result<Self, SpiralError> {
+        let data = deserialize_header(params, data, params.setup_bytes())?;
 
         let mut idx = 0;
 
-        let seed = data[0..SEED_LENGTH].try_into().unwrap();
+        let seed = take_bytes(data, SEED_LENGTH)?.try_into().unwrap();
         let mut rng = ChaCha20Rng::from_seed(seed);
         idx += SEED_LENGTH;
 
         let mut v_packing = new_vec_raw(params, params.n, params.n + 1, params.t_conv);
-        idx += deserialize_vec_polymatrix_rng(&mut v_packing, &data[idx..], &mut rng);
+        idx += deserialize_vec_polymatrix_rng(&mut v_packing, &data[idx..], &mut rng)?;
 
         if params.expand_queries {
             let mut v_expansion_left = new_vec_raw(params, params.g(), 2, params.t_exp_left);
-            idx += deserialize_vec_polymatrix_rng(&mut v_expansion_left, &data[idx..], &mut rng);
+            idx += deserialize_vec_polymatrix_rng(&mut v_expansion_left, &data[idx..], &mut rng)?;
 
             let mut v_expansion_right =
                 new_vec_raw(params, params.stop_round() + 1, 2, params.t_exp_right);
-            idx += deserialize_vec_polymatrix_rng(&mut v_expansion_right, &data[idx..], &mut rng);
+            idx += deserialize_vec_polymatrix_rng(&mut v_expansion_right, &data[idx..], &mut rng)?;
 
             let mut v_conversion = new_vec_raw(params, 1, 2, 2 * params.t_conv);
-            _ = deserialize_vec_polymatrix_rng(&mut v_conversion, &data[idx..], &mut rng);
+            deserialize_vec_polymatrix_rng(&mut v_conversion, &data[idx..], &mut rng)?;
 
-            Self {
+            Ok(Self {
                 v_packing: Self::to_ntt_alloc_vec(&v_packing).unwrap(),
                 v_expansion_left: Self::to_ntt_alloc_vec(&v_expansion_left),
                 v_expansion_right: Self::to_ntt_alloc_vec(&v_expansion_right),
                 v_conversion: Self::to_ntt_alloc_vec(&v_conversion),
                 seed: Some(seed),
-            }
+                fingerprint: params.fingerprint(),
+            })
         } else {
-            Self {
+            Ok(Self {
                 v_packing: Self::to_ntt_alloc_vec(&v_packing).unwrap(),
                 v_expansion_left: None,
                 v_expansion_right: None,
                 v_conversion: None,
                 seed: Some(seed),
-            }
+                fingerprint: params.fingerprint(),
+            })
         }
     }
 }
 
-pub struct Query<'a> {
-    pub ct: Option<PolyMatrixRaw<'a>>,
+pub struct Query {
+    pub ct: Option<PolyMatrixRaw>,
     pub v_buf: Option<Vec<u64>>,
-    pub v_ct: Option<Vec<PolyMatrixRaw<'a>>>,
+    pub v_ct: Option<Vec<PolyMatrixRaw>>,
     pub seed: Option<Seed>,
+    pub fingerprint: u64,
 }
 
-impl<'a> Query<'a> {
+impl Query {
     pub fn empty() -> Self {
         Query {
             ct: None,
             v_ct: None,
             v_buf: None,
             seed: None,
+            fingerprint: 0,
         }
     }
 
     pub fn serialize(&self) -> Vec<u8> {
         let mut data = Vec::new();
+        data.extend(self.fingerprint.to_le_bytes());
         if self.seed.is_some() {
             let seed = self.seed.as_ref().unwrap();
             data.extend(seed);
@@ -253,41 +366,55 @@ impl<'a> Query<'a> {
         data
     }
 
-    pub fn deserialize(params: &'a Params, mut data: &[u8]) -> Self {
-        assert_eq!(params.query_bytes(), data.len());
+    pub fn deserialize(params: &Arc<Params>, data: &[u8]) -> Result<Self, SpiralError> {
+        let mut data = deserialize_header(params, data, params.query_bytes())?;
 
         let mut out = Query::empty();
-        let seed = data[0..SEED_LENGTH].try_into().unwrap();
+        out.fingerprint = params.fingerprint();
+        let seed = take_bytes(data, SEED_LENGTH)?.try_into().unwrap();
         out.seed = Some(seed);
         let mut rng = ChaCha20Rng::from_seed(seed);
         data = &data[SEED_LENGTH..];
         if params.expand_queries {
             let mut ct = PolyMatrixRaw::zero(params, 2, 1);
-            deserialize_polymatrix_rng(&mut ct, data, &mut rng);
+            deserialize_polymatrix_rng(&mut ct, data, &mut rng)?;
             out.ct = Some(ct);
         } else {
             let v_buf_bytes = params.query_v_buf_bytes();
-            let v_buf: Vec<u64> = (&data[..v_buf_bytes])
+            let v_buf = take_bytes(data, v_buf_bytes)?
                 .chunks(size_of::<u64>())
-                .map(|x| u64::from_ne_bytes(x.try_into().unwrap()))
-                .collect();
+                .map(|x| check_packed_residues(params, u64::from_ne_bytes(x.try_into().unwrap())))
+                .collect::<Result<Vec<u64>, SpiralError>>()?;
             let v_buf_interleaved = interleave_rng_data(params, &v_buf, &mut rng);
             out.v_buf = Some(v_buf_interleaved);
 
             let mut v_ct = new_vec_raw(params, params.db_dim_2, 2, 2 * params.t_gsw);
-            deserialize_vec_polymatrix_rng(&mut v_ct, &data[v_buf_bytes..], &mut rng);
+            deserialize_vec_polymatrix_rng(&mut v_ct, &data[v_buf_bytes..], &mut rng)?;
             out.v_ct = Some(v_ct);
         }
-        out
+        Ok(out)
     }
 }
 
-fn matrix_with_identity<'a>(p: &PolyMatrixRaw<'a>) -> PolyMatrixRaw<'a> {
-    assert_eq!(p.cols, 1);
-    let mut r = PolyMatrixRaw::zero(p.params, p.rows, p.rows + 1);
-    r.copy_into(p, 0, 0);
-    r.copy_into(&PolyMatrixRaw::identity(p.params, p.rows, p.rows), 0, 1);
-    r
+fn matrix_with_identity(
+    params: &Arc<Params>,
+    rows: usize,
+    alloc: &impl Fn(usize) -> io::Result<AlignedMemory64>,
+) -> io::Result<PolyMatrixRaw> {
+    let mut r = PolyMatrixRaw {
+        params: params.clone(),
+        rows,
+        cols: rows + 1,
+        data: alloc(rows * (rows + 1) * params.poly_len)?,
+    };
+    r.copy_into(&PolyMatrixRaw::identity(params, rows, rows), 0, 1);
+    Ok(r)
+}
+
+// Overwrites the key and buffered output of an rng that produced secrets
+fn wipe_rng(rng: &mut ChaCha20Rng) {
+    unsafe { ptr::write_volatile(rng, ChaCha20Rng::from_seed(Seed::default())) };
+    compiler_fence(Ordering::SeqCst);
 }
 
 fn params_with_moduli(params: &Params, moduli: &Vec<u64>) -> Params {
@@ -308,70 +435,200 @@ fn params_with_moduli(params: &Params, moduli: &Vec<u64>) -> Params {
         params.instances,
         params.db_item_size,
     )
+    .unwrap()
+}
+
+// How far each coefficient of a decoded response lay from the nearest multiple of the
+// plaintext scale before rounding. `threshold` is half the scale, so the noise never exceeds
+// it: a coefficient whose true noise passed it simply rounds to the wrong multiple. A wrong
+// decode therefore shows up as noise spread across the whole [0, threshold] range, rather
+// than the small values of an honest response.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodingNoise {
+    pub noise: Vec<u64>,
+    pub threshold: u64,
+}
+
+impl DecodingNoise {
+    pub fn max(&self) -> u64 {
+        self.noise.iter().copied().max().unwrap_or(0)
+    }
+
+    pub fn max_ratio(&self) -> f64 {
+        self.max() as f64 / self.threshold as f64
+    }
+
+    pub fn mean_ratio(&self) -> f64 {
+        let sum: f64 = self.noise.iter().map(|&x| x as f64).sum();
+        sum / (self.noise.len() as f64 * self.threshold as f64)
+    }
+
+    // Honest responses stay below NOISE_SUSPECT_RATIO, peaking near 0.3 on CFG_20_256 (see
+    // decoding_noise_of_honest_responses_is_low_20_256, ignored by default for its 2 GiB
+    // database); those decoded under the wrong key or parameters spread over the whole range
+    // and pass it
+    pub fn is_suspect(&self) -> bool {
+        self.max_ratio() >= NOISE_SUSPECT_RATIO
+    }
+}
+
+// What decoding needs besides the response: the parameters over q2, whose NTT tables are
+// costly to build, and the secret key switched to q2
+struct ResponseDecoder {
+    q2_params: Arc<Params>,
+    sk_gsw_q2_ntt: PolyMatrixNTT,
 }
 
-pub struct Client<'a> {
-    params: &'a Params,
-    sk_gsw: PolyMatrixRaw<'a>,
-    sk_reg: PolyMatrixRaw<'a>,
-    sk_gsw_full: PolyMatrixRaw<'a>,
-    sk_reg_full: PolyMatrixRaw<'a>,
+impl ResponseDecoder {
+    fn init(
+        params: &Params,
+        alloc: &impl Fn(usize) -> io::Result<AlignedMemory64>,
+    ) -> io::Result<Self> {
+        let q2_params = Arc::new(params_with_moduli(params, &vec![params.q2]));
+        let (rows, cols) = params.get_sk_gsw();
+        let sk_gsw_q2_ntt = PolyMatrixNTT {
+            params: q2_params.clone(),
+            rows,
+            cols,
+            data: alloc(rows * cols * q2_params.crt_count * q2_params.poly_len)?,
+        };
+        Ok(Self {
+            q2_params,
+            sk_gsw_q2_ntt,
+        })
+    }
+
+    fn update(&mut self, sk_gsw: &PolyMatrixRaw) {
+        let params = &sk_gsw.params;
+        let mut sk_gsw_q2 = PolyMatrixRaw::zero_secret(&self.q2_params, sk_gsw.rows, sk_gsw.cols);
+        for (x, &y) in sk_gsw_q2
+            .data
+            .as_mut_slice()
+            .iter_mut()
+            .zip(sk_gsw.data.as_slice())
+        {
+            *x = recenter(y, params.modulus, params.q2);
+        }
+        to_ntt(&mut self.sk_gsw_q2_ntt, &sk_gsw_q2);
+    }
+}
+
+// Encryptions of zero under the client's keys, waiting for a query index
+struct PrecomputedQuery {
+    seed: Seed,
+    zero_cts: Vec<PolyMatrixNTT>,
+}
+
+pub struct Client {
+    params: Arc<Params>,
+    sk_gsw: PolyMatrixRaw,
+    sk_reg: PolyMatrixRaw,
+    sk_gsw_full: PolyMatrixRaw,
+    sk_reg_full: PolyMatrixRaw,
+    sk_gsw_ntt: PolyMatrixNTT,
+    sk_reg_ntt: PolyMatrixNTT,
+    decoder: ResponseDecoder,
+    query_pool: VecDeque<PrecomputedQuery>,
     dg: DiscreteGaussian,
 }
 
-impl<'a> Client<'a> {
-    pub fn init(params: &'a Params) -> Self {
-        let sk_gsw_dims = params.get_sk_gsw();
-        let sk_reg_dims = params.get_sk_reg();
-        let sk_gsw = PolyMatrixRaw::zero(params, sk_gsw_dims.0, sk_gsw_dims.1);
-        let sk_reg = PolyMatrixRaw::zero(params, sk_reg_dims.0, sk_reg_dims.1);
-        let sk_gsw_full = matrix_with_identity(&sk_gsw);
-        let sk_reg_full = matrix_with_identity(&sk_reg);
+impl Client {
+    pub fn init(params: &Arc<Params>) -> Self {
+        Self::init_with(params, &|sz| Ok(AlignedMemory64::new_secret(sz))).unwrap()
+    }
+
+    // Like `init`, but keeps the secret keys in memory that is locked into RAM
+    pub fn init_locked(params: &Arc<Params>) -> io::Result<Self> {
+        Self::init_with(params, &AlignedMemory64::new_locked)
+    }
+
+    fn init_with(
+        params: &Arc<Params>,
+        alloc: &impl Fn(usize) -> io::Result<AlignedMemory64>,
+    ) -> io::Result<Self> {
+        let sk_matrix = |(rows, cols): (usize, usize)| -> io::Result<PolyMatrixRaw> {
+            Ok(PolyMatrixRaw {
+                params: params.clone(),
+                rows,
+                cols,
+                data: alloc(rows * cols * params.poly_len)?,
+            })
+        };
+        let sk_ntt_matrix = |(rows, cols): (usize, usize)| -> io::Result<PolyMatrixNTT> {
+            Ok(PolyMatrixNTT {
+                params: params.clone(),
+                rows,
+                cols,
+                data: alloc(rows * cols * params.crt_count * params.poly_len)?,
+            })
+        };
+        let sk_gsw = sk_matrix(params.get_sk_gsw())?;
+        let sk_reg = sk_matrix(params.get_sk_reg())?;
+        let sk_gsw_full = matrix_with_identity(params, sk_gsw.rows, alloc)?;
+        let sk_reg_full = matrix_with_identity(params, sk_reg.rows, alloc)?;
+        let sk_gsw_ntt = sk_ntt_matrix(params.get_sk_gsw())?;
+        let sk_reg_ntt = sk_ntt_matrix(params.get_sk_reg())?;
+        let decoder = ResponseDecoder::init(params, alloc)?;
 
         let dg = DiscreteGaussian::init(params);
 
-        Self {
-            params,
+        Ok(Self {
+            params: params.clone(),
             sk_gsw,
             sk_reg,
             sk_gsw_full,
             sk_reg_full,
+            sk_gsw_ntt,
+            sk_reg_ntt,
+            decoder,
+            query_pool: VecDeque::new(),
             dg,
-        }
+        })
+    }
+
+    // Recomputes everything derived from the secret keys. sk_gsw_full and sk_reg_full are
+    // [sk | I], so only their first column follows the keys.
+    fn update_derived_keys(&mut self) {
+        self.sk_gsw_full.copy_into(&self.sk_gsw, 0, 0);
+        self.sk_reg_full.copy_into(&self.sk_reg, 0, 0);
+        to_ntt(&mut self.sk_gsw_ntt, &self.sk_gsw);
+        to_ntt(&mut self.sk_reg_ntt, &self.sk_reg);
+        self.decoder.update(&self.sk_gsw);
+        self.query_pool.clear();
     }
 
     #[allow(dead_code)]
-    pub(crate) fn get_sk_reg(&self) -> &PolyMatrixRaw<'a> {
+    pub(crate) fn get_sk_reg(&self) -> &PolyMatrixRaw {
         &self.sk_reg
     }
 
-    fn get_fresh_gsw_public_key(
+    fn get_fresh_gsw_public_key<R: RngCore + CryptoRng>(
         &self,
         m: usize,
-        rng: &mut ChaCha20Rng,
+        rng: &mut R,
         rng_pub: &mut ChaCha20Rng,
-    ) -> PolyMatrixRaw<'a> {
-        let params = self.params;
+    ) -> PolyMatrixRaw {
+        let params = &self.params;
         let n = params.n;
 
         let a = PolyMatrixRaw::random_rng(params, 1, m, rng_pub);
         let e = PolyMatrixRaw::noise(params, n, m, &self.dg, rng);
         let a_inv = -&a;
-        let b_p = &self.sk_gsw.ntt() * &a.ntt();
+        let b_p = &self.sk_gsw_ntt * &a.ntt();
         let b = &e.ntt() + &b_p;
         let p = stack(&a_inv, &b.raw());
         p
     }
 
-    fn get_regev_sample(
+    fn get_regev_sample<R: RngCore + CryptoRng>(
         &self,
-        rng: &mut ChaCha20Rng,
+        rng: &mut R,
         rng_pub: &mut ChaCha20Rng,
-    ) -> PolyMatrixNTT<'a> {
-        let params = self.params;
+    ) -> PolyMatrixNTT {
+        let params = &self.params;
         let a = PolyMatrixRaw::random_rng(params, 1, 1, rng_pub);
         let e = PolyMatrixRaw::noise(params, 1, 1, &self.dg, rng);
-        let b_p = &self.sk_reg.ntt() * &a.ntt();
+        let b_p = &self.sk_reg_ntt * &a.ntt();
         let b = &e.ntt() + &b_p;
         let mut p = PolyMatrixNTT::zero(params, 2, 1);
         p.copy_into(&(-&a).ntt(), 0, 0);
@@ -379,13 +636,13 @@ impl<'a> Client<'a> {
         p
     }
 
-    fn get_fresh_reg_public_key(
+    fn get_fresh_reg_public_key<R: RngCore + CryptoRng>(
         &self,
         m: usize,
-        rng: &mut ChaCha20Rng,
+        rng: &mut R,
         rng_pub: &mut ChaCha20Rng,
-    ) -> PolyMatrixNTT<'a> {
-        let params = self.params;
+    ) -> PolyMatrixNTT {
+        let params = &self.params;
 
         let mut p = PolyMatrixNTT::zero(params, 2, m);
 
@@ -395,45 +652,45 @@ impl<'a> Client<'a> {
         p
     }
 
-    fn encrypt_matrix_gsw(
+    fn encrypt_matrix_gsw<R: RngCore + CryptoRng>(
         &self,
-        ag: &PolyMatrixNTT<'a>,
-        rng: &mut ChaCha20Rng,
+        ag: &PolyMatrixNTT,
+        rng: &mut R,
         rng_pub: &mut ChaCha20Rng,
-    ) -> PolyMatrixNTT<'a> {
+    ) -> PolyMatrixNTT {
         let mx = ag.cols;
         let p = self.get_fresh_gsw_public_key(mx, rng, rng_pub);
         let res = &(p.ntt()) + &(ag.pad_top(1));
         res
     }
 
-    pub fn encrypt_matrix_reg(
+    pub fn encrypt_matrix_reg<R: RngCore + CryptoRng>(
         &self,
-        a: &PolyMatrixNTT<'a>,
-        rng: &mut ChaCha20Rng,
+        a: &PolyMatrixNTT,
+        rng: &mut R,
         rng_pub: &mut ChaCha20Rng,
-    ) -> PolyMatrixNTT<'a> {
+    ) -> PolyMatrixNTT {
         let m = a.cols;
         let p = self.get_fresh_reg_public_key(m, rng, rng_pub);
         &p + &a.pad_top(1)
     }
 
-    pub fn decrypt_matrix_reg(&self, a: &PolyMatrixNTT<'a>) -> PolyMatrixNTT<'a> {
+    pub fn decrypt_matrix_reg(&self, a: &PolyMatrixNTT) -> PolyMatrixNTT {
         &self.sk_reg_full.ntt() * a
     }
 
-    pub fn decrypt_matrix_gsw(&self, a: &PolyMatrixNTT<'a>) -> PolyMatrixNTT<'a> {
+    pub fn decrypt_matrix_gsw(&self, a: &PolyMatrixNTT) -> PolyMatrixNTT {
         &self.sk_gsw_full.ntt() * a
     }
 
-    fn generate_expansion_params(
+    fn generate_expansion_params<R: RngCore + CryptoRng>(
         &self,
         num_exp: usize,
         m_exp: usize,
-        rng: &mut ChaCha20Rng,
+        rng: &mut R,
         rng_pub: &mut ChaCha20Rng,
-    ) -> Vec<PolyMatrixNTT<'a>> {
-        let params = self.params;
+    ) -> Vec<PolyMatrixNTT> {
+        let params = &self.params;
         let g_exp = build_gadget(params, 1, m_exp);
         let g_exp_ntt = g_exp.ntt();
         let mut res = Vec::new();
@@ -448,49 +705,96 @@ impl<'a> Client<'a> {
         res
     }
 
-    pub fn generate_keys_from_seed(&mut self, seed: Seed) -> PublicParameters<'a> {
-        self.generate_keys_impl(&mut ChaCha20Rng::from_seed(seed))
+    // Derives the keys from `seed`, which is zeroized once the rng has been seeded
+    pub fn generate_keys_from_seed(&mut self, seed: &mut Seed) -> PublicParameters {
+        let mut rng = ChaCha20Rng::from_seed(*seed);
+        seed.zeroize();
+        let pp = self.generate_keys_rng(&mut rng);
+        wipe_rng(&mut rng);
+        pp
     }
 
-    pub fn generate_keys(&mut self) -> PublicParameters<'a> {
-        self.generate_keys_impl(&mut ChaCha20Rng::from_entropy())
+    pub fn generate_keys(&mut self) -> PublicParameters {
+        let mut rng = ChaCha20Rng::from_entropy();
+        let pp = self.generate_keys_rng(&mut rng);
+        wipe_rng(&mut rng);
+        pp
     }
 
-    pub fn generate_secret_keys_from_seed(&mut self, seed: Seed) {
-        self.generate_secret_keys_impl(&mut ChaCha20Rng::from_seed(seed))
+    pub fn generate_secret_keys_from_seed(&mut self, seed: &mut Seed) {
+        let mut rng = ChaCha20Rng::from_seed(*seed);
+        seed.zeroize();
+        self.generate_secret_keys_rng(&mut rng);
+        wipe_rng(&mut rng);
     }
 
     pub fn generate_secret_keys(&mut self) {
-        self.generate_secret_keys_impl(&mut ChaCha20Rng::from_entropy())
+        let mut rng = ChaCha20Rng::from_entropy();
+        self.generate_secret_keys_rng(&mut rng);
+        wipe_rng(&mut rng);
+    }
+
+    // Encodes the secret keys as a version byte, the parameter fingerprint, and the
+    // coefficients of sk_gsw and then sk_reg as little-endian u64s
+    pub fn export_secret_key(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.params.secret_key_bytes());
+        data.push(SECRET_KEY_VERSION);
+        data.extend(self.params.fingerprint().to_le_bytes());
+        for sk in [&self.sk_gsw, &self.sk_reg] {
+            data.extend(sk.data.as_slice().iter().flat_map(|x| x.to_le_bytes()));
+        }
+        data
     }
 
-    fn generate_secret_keys_impl(&mut self, rng: &mut ChaCha20Rng) {
+    pub fn from_secret_key(params: &Arc<Params>, data: &[u8]) -> Result<Self, SpiralError> {
+        let version = take_bytes(data, 1)?[0];
+        if version != SECRET_KEY_VERSION {
+            return Err(SpiralError::UnsupportedVersion { version });
+        }
+        let data = deserialize_header(params, &data[1..], params.secret_key_bytes() - 1)?;
+
+        let mut client = Client::init(params);
+        let mut coeffs = data
+            .chunks(size_of::<u64>())
+            .map(|x| check_coeff(u64::from_le_bytes(x.try_into().unwrap()), params.modulus));
+        for sk in [&mut client.sk_gsw, &mut client.sk_reg] {
+            for x in sk.data.as_mut_slice() {
+                *x = coeffs.next().unwrap()?;
+            }
+        }
+        client.update_derived_keys();
+        Ok(client)
+    }
+
+    // Draws the secret keys from `rng`; the keys are the first thing every key generation
+    // draws, so a seed gives the same secret keys here and in `generate_keys_rng`
+    pub fn generate_secret_keys_rng<R: RngCore + CryptoRng>(&mut self, rng: &mut R) {
         self.dg.sample_matrix(&mut self.sk_gsw, rng);
         self.dg.sample_matrix(&mut self.sk_reg, rng);
-        self.sk_gsw_full = matrix_with_identity(&self.sk_gsw);
-        self.sk_reg_full = matrix_with_identity(&self.sk_reg);
+        self.update_derived_keys();
     }
 
-    fn generate_keys_impl(&mut self, rng: &mut ChaCha20Rng) -> PublicParameters<'a> {
-        let params = self.params;
+    // Draws the secret keys, the public seed and all noise from `rng`, so the same rng state
+    // always gives the same public parameters
+    pub fn generate_keys_rng<R: RngCore + CryptoRng>(&mut self, rng: &mut R) -> PublicParameters {
+        let params = self.params.clone();
 
-        self.generate_secret_keys_impl(rng);
-        let sk_reg_ntt = to_ntt_alloc(&self.sk_reg);
+        self.generate_secret_keys_rng(rng);
+        let sk_reg_ntt = &self.sk_reg_ntt;
 
-        let mut rng = ChaCha20Rng::from_entropy();
-        let mut pp = PublicParameters::init(params);
+        let mut pp = PublicParameters::init(&params);
         let pp_seed = rng.gen();
         pp.seed = Some(pp_seed);
         let mut rng_pub = ChaCha20Rng::from_seed(pp_seed);
 
         // Params for packing
-        let gadget_conv = build_gadget(params, 1, params.t_conv);
+        let gadget_conv = build_gadget(&params, 1, params.t_conv);
         let gadget_conv_ntt = to_ntt_alloc(&gadget_conv);
         for i in 0..params.n {
-            let scaled = scalar_multiply_alloc(&sk_reg_ntt, &gadget_conv_ntt);
-            let mut ag = PolyMatrixNTT::zero(params, params.n, params.t_conv);
+            let scaled = scalar_multiply_alloc(sk_reg_ntt, &gadget_conv_ntt);
+            let mut ag = PolyMatrixNTT::zero(&params, params.n, params.t_conv);
             ag.copy_into(&scaled, i, 0);
-            let w = self.encrypt_matrix_gsw(&ag, &mut rng, &mut rng_pub);
+            let w = self.encrypt_matrix_gsw(&ag, rng, &mut rng_pub);
             pp.v_packing.push(w);
         }
 
@@ -499,22 +803,21 @@ impl<'a> Client<'a> {
             pp.v_expansion_left = Some(self.generate_expansion_params(
                 params.g(),
                 params.t_exp_left,
-                &mut rng,
+                rng,
                 &mut rng_pub,
             ));
             pp.v_expansion_right = Some(self.generate_expansion_params(
                 params.stop_round() + 1,
                 params.t_exp_right,
-                &mut rng,
+                rng,
                 &mut rng_pub,
             ));
 
             // Params for converison
-            let g_conv = build_gadget(params, 2, 2 * params.t_conv);
-            let sk_reg_ntt = self.sk_reg.ntt();
-            let sk_reg_squared_ntt = &sk_reg_ntt * &sk_reg_ntt;
+            let g_conv = build_gadget(&params, 2, 2 * params.t_conv);
+            let sk_reg_squared_ntt = sk_reg_ntt * sk_reg_ntt;
             pp.v_conversion = Some(Vec::from_iter(once(PolyMatrixNTT::zero(
-                params,
+                &params,
                 2,
                 2 * params.t_conv,
             ))));
@@ -522,12 +825,12 @@ impl<'a> Client<'a> {
                 let sigma;
                 if i % 2 == 0 {
                     let val = g_conv.get_poly(0, i)[0];
-                    sigma = &sk_reg_squared_ntt * &single_poly(params, val).ntt();
+                    sigma = &sk_reg_squared_ntt * &single_poly(&params, val).ntt();
                 } else {
                     let val = g_conv.get_poly(1, i)[0];
-                    sigma = &sk_reg_ntt * &single_poly(params, val).ntt();
+                    sigma = sk_reg_ntt * &single_poly(&params, val).ntt();
                 }
-                let ct = self.encrypt_matrix_reg(&sigma, &mut rng, &mut rng_pub);
+                let ct = self.encrypt_matrix_reg(&sigma, rng, &mut rng_pub);
                 pp.v_conversion.as_mut().unwrap()[0].copy_into(&ct, 0, i);
             }
         }
@@ -535,20 +838,56 @@ impl<'a> Client<'a> {
         pp
     }
 
-    pub fn generate_query(&self, idx_target: usize) -> Query<'a> {
-        let params = self.params;
+    pub fn generate_query(&self, idx_target: usize) -> Result<Query, SpiralError> {
+        let mut rng = ChaCha20Rng::from_entropy();
+        let query = self.generate_query_rng(idx_target, &mut rng);
+        wipe_rng(&mut rng);
+        query
+    }
+
+    // Draws the public seed and all noise of the query from `rng`; an index past the last
+    // item is rejected before anything is drawn
+    pub fn generate_query_rng<R: RngCore + CryptoRng>(
+        &self,
+        idx_target: usize,
+        rng: &mut R,
+    ) -> Result<Query, SpiralError> {
+        check_index(&self.params, idx_target)?;
+        Ok(self.finish_query(self.precompute_query_rng(rng), idx_target))
+    }
+
+    // Everything a query needs that does not depend on its index: the public seed and the
+    // encryptions of zero that the index is later added to. The encryptions are drawn in the
+    // order the query lays them out, so a precomputed query finishes to exactly the query
+    // `generate_query_rng` would give for the same rng.
+    fn precompute_query_rng<R: RngCore + CryptoRng>(&self, rng: &mut R) -> PrecomputedQuery {
+        let params = &self.params;
+        let seed = rng.gen();
+        let mut rng_pub = ChaCha20Rng::from_seed(seed);
+        let num_cts = if params.expand_queries {
+            1
+        } else {
+            (1 << params.db_dim_1) + params.db_dim_2 * 2 * params.t_gsw
+        };
+        let zero_cts = (0..num_cts)
+            .map(|_| self.get_fresh_reg_public_key(1, rng, &mut rng_pub))
+            .collect();
+        PrecomputedQuery { seed, zero_cts }
+    }
+
+    fn finish_query(&self, precomputed: PrecomputedQuery, idx_target: usize) -> Query {
+        let params = &self.params;
         let further_dims = params.db_dim_2;
         let idx_dim0 = idx_target / (1 << further_dims);
         let idx_further = idx_target % (1 << further_dims);
         let scale_k = params.modulus / params.pt_modulus;
         let bits_per = get_bits_per(params, params.t_gsw);
 
-        let mut rng = ChaCha20Rng::from_entropy();
-
         let mut query = Query::empty();
-        let query_seed = ChaCha20Rng::from_entropy().gen();
-        query.seed = Some(query_seed);
-        let mut rng_pub = ChaCha20Rng::from_seed(query_seed);
+        query.fingerprint = params.fingerprint();
+        query.seed = Some(precomputed.seed);
+        let mut zero_cts = precomputed.zero_cts.iter();
+        let mut encrypt = |sigma: &PolyMatrixNTT| zero_cts.next().unwrap() + &sigma.pad_top(1);
         if params.expand_queries {
             // pack query into single ciphertext
             let mut sigma = PolyMatrixRaw::zero(params, 1, 1);
@@ -581,11 +920,7 @@ impl<'a> Client<'a> {
                 }
             }
 
-            query.ct = Some(from_ntt_alloc(&self.encrypt_matrix_reg(
-                &to_ntt_alloc(&sigma),
-                &mut rng,
-                &mut rng_pub,
-            )));
+            query.ct = Some(from_ntt_alloc(&encrypt(&to_ntt_alloc(&sigma))));
         } else {
             let num_expanded = 1 << params.db_dim_1;
             let mut sigma_v = Vec::<PolyMatrixNTT>::new();
@@ -597,14 +932,10 @@ impl<'a> Client<'a> {
             for i in 0..num_expanded {
                 let value = ((i == idx_dim0) as u64) * scale_k;
                 let sigma = PolyMatrixRaw::single_value(&params, value);
-                reg_cts.push(self.encrypt_matrix_reg(
-                    &to_ntt_alloc(&sigma),
-                    &mut rng,
-                    &mut rng_pub,
-                ));
+                reg_cts.push(encrypt(&to_ntt_alloc(&sigma)));
             }
             // reorient into server's preferred indexing
-            reorient_reg_ciphertexts(self.params, reg_cts_buf.as_mut_slice(), &reg_cts);
+            reorient_reg_ciphertexts(&self.params, reg_cts_buf.as_mut_slice(), &reg_cts);
 
             // generate GSW ciphertexts
             for i in 0..further_dims {
@@ -616,26 +947,99 @@ impl<'a> Client<'a> {
                     let sigma = PolyMatrixRaw::single_value(&params, value);
                     let sigma_ntt = to_ntt_alloc(&sigma);
 
-                    // important to rng in the right order here
-                    let prod = &to_ntt_alloc(&self.sk_reg) * &sigma_ntt;
-                    let ct = &self.encrypt_matrix_reg(&prod, &mut rng, &mut rng_pub);
+                    // important to use the encryptions in the right order here
+                    let prod = &self.sk_reg_ntt * &sigma_ntt;
+                    let ct = &encrypt(&prod);
                     ct_gsw.copy_into(ct, 0, 2 * j);
 
-                    let ct = &self.encrypt_matrix_reg(&sigma_ntt, &mut rng, &mut rng_pub);
+                    let ct = &encrypt(&sigma_ntt);
                     ct_gsw.copy_into(ct, 0, 2 * j + 1);
                 }
                 sigma_v.push(ct_gsw);
             }
 
             query.v_buf = Some(reg_cts_buf);
-            query.v_ct = Some(sigma_v.iter().map(|x| from_ntt_alloc(x)).collect());
+            query.v_ct = Some(sigma_v.iter().map(from_ntt_alloc).collect());
         }
         query
     }
 
-    pub fn decode_response(&self, data: &[u8]) -> Vec<u8> {
+    // Fills the pool of precomputed queries up to `count`, so that the next queries only
+    // cost the online step. The pool is emptied whenever the keys change.
+    pub fn precompute_queries(&mut self, count: usize) {
+        let mut rng = ChaCha20Rng::from_entropy();
+        self.precompute_queries_rng(count, &mut rng);
+        wipe_rng(&mut rng);
+    }
+
+    pub fn precompute_queries_rng<R: RngCore + CryptoRng>(&mut self, count: usize, rng: &mut R) {
+        while self.query_pool.len() < count {
+            let precomputed = self.precompute_query_rng(rng);
+            self.query_pool.push_back(precomputed);
+        }
+    }
+
+    pub fn num_precomputed_queries(&self) -> usize {
+        self.query_pool.len()
+    }
+
+    // Finishes the oldest precomputed query for `idx_target`, or generates a fresh query if
+    // the pool is empty. Each precomputed query is used at most once.
+    pub fn generate_query_from_pool(&mut self, idx_target: usize) -> Result<Query, SpiralError> {
+        check_index(&self.params, idx_target)?;
+        match self.query_pool.pop_front() {
+            Some(precomputed) => Ok(self.finish_query(precomputed, idx_target)),
+            None => self.generate_query(idx_target),
+        }
+    }
+
+    // Generates one query per index, or none if any index is out of range. The queries are
+    // exactly those that calling `generate_query` once per index would give, in order.
+    pub fn generate_queries(&self, idx_targets: &[usize]) -> Result<Vec<Query>, SpiralError> {
+        let mut rng = ChaCha20Rng::from_entropy();
+        let queries = self.generate_queries_rng(idx_targets, &mut rng);
+        wipe_rng(&mut rng);
+        queries
+    }
+
+    pub fn generate_queries_rng<R: RngCore + CryptoRng>(
+        &self,
+        idx_targets: &[usize],
+        rng: &mut R,
+    ) -> Result<Vec<Query>, SpiralError> {
+        for &idx_target in idx_targets {
+            check_index(&self.params, idx_target)?;
+        }
+        idx_targets
+            .iter()
+            .map(|&idx_target| self.generate_query_rng(idx_target, rng))
+            .collect()
+    }
+
+    // Decodes responses to queries from `generate_queries`, stopping at the first that is
+    // malformed
+    pub fn decode_responses<T: AsRef<[u8]>>(
+        &self,
+        responses: &[T],
+    ) -> Result<Vec<Vec<u8>>, SpiralError> {
+        responses
+            .iter()
+            .map(|data| self.decode_response(data.as_ref()))
+            .collect()
+    }
+
+    pub fn decode_response(&self, data: &[u8]) -> Result<Vec<u8>, SpiralError> {
+        self.decode_response_with_noise(data).map(|(item, _)| item)
+    }
+
+    // Like `decode_response`, but also reports how close each coefficient came to rounding
+    // to the wrong value
+    pub fn decode_response_with_noise(
+        &self,
+        data: &[u8],
+    ) -> Result<(Vec<u8>, DecodingNoise), SpiralError> {
         /*
-            0. NTT over q2 the secret key
+            0. NTT over q2 the secret key (done once the keys are set)
 
             1. read first row in q2_bit chunks
             2. read rest in q1_bit chunks
@@ -643,44 +1047,44 @@ impl<'a> Client<'a> {
             4. Multiply the results of (0) and (3)
             5. Divide and round correctly
         */
-        let params = self.params;
+        let params = &self.params;
+        let data = deserialize_header(params, data, params.response_bytes())?;
+
         let p = params.pt_modulus;
         let p_bits = log2_ceil(params.pt_modulus);
         let q1 = 4 * params.pt_modulus;
         let q1_bits = log2_ceil(q1) as usize;
-        let q2 = Q2_VALUES[params.q2_bits as usize];
+        let q2 = params.q2;
         let q2_bits = params.q2_bits as usize;
 
-        let q2_params = params_with_moduli(params, &vec![q2]);
-
-        // this only needs to be done during keygen
-        let mut sk_gsw_q2 = PolyMatrixRaw::zero(&q2_params, params.n, 1);
-        for i in 0..params.poly_len * params.n {
-            sk_gsw_q2.data[i] = recenter(self.sk_gsw.data[i], params.modulus, q2);
-        }
-        let mut sk_gsw_q2_ntt = PolyMatrixNTT::zero(&q2_params, params.n, 1);
-        to_ntt(&mut sk_gsw_q2_ntt, &sk_gsw_q2);
+        let q2_params = &self.decoder.q2_params;
+        let sk_gsw_q2_ntt = &self.decoder.sk_gsw_q2_ntt;
 
         let mut result = PolyMatrixRaw::zero(&params, params.instances * params.n, params.n);
+        let denom = (q2 * (q1 / p)) as i64;
+        let mut noise = DecodingNoise {
+            noise: vec![0; result.data.len()],
+            threshold: (denom / 2) as u64,
+        };
 
         let mut bit_offs = 0;
         for instance in 0..params.instances {
             // this must be done during decoding
-            let mut first_row = PolyMatrixRaw::zero(&q2_params, 1, params.n);
+            let mut first_row = PolyMatrixRaw::zero(q2_params, 1, params.n);
             let mut rest_rows = PolyMatrixRaw::zero(&params, params.n, params.n);
             for i in 0..params.n * params.poly_len {
-                first_row.data[i] = read_arbitrary_bits(data, bit_offs, q2_bits);
+                first_row.data[i] = check_coeff(read_arbitrary_bits(data, bit_offs, q2_bits), q2)?;
                 bit_offs += q2_bits;
             }
             for i in 0..params.n * params.n * params.poly_len {
-                rest_rows.data[i] = read_arbitrary_bits(data, bit_offs, q1_bits);
+                rest_rows.data[i] = check_coeff(read_arbitrary_bits(data, bit_offs, q1_bits), q1)?;
                 bit_offs += q1_bits;
             }
 
-            let mut first_row_q2 = PolyMatrixNTT::zero(&q2_params, 1, params.n);
+            let mut first_row_q2 = PolyMatrixNTT::zero(q2_params, 1, params.n);
             to_ntt(&mut first_row_q2, &first_row);
 
-            let sk_prod = (&sk_gsw_q2_ntt * &first_row_q2).raw();
+            let sk_prod = (sk_gsw_q2_ntt * &first_row_q2).raw();
 
             let q1_i64 = q1 as i64;
             let q2_i64 = q2 as i64;
@@ -695,22 +1099,24 @@ impl<'a> Client<'a> {
                     val_rest -= q1_i64;
                 }
 
-                let denom = (q2 * (q1 / p)) as i64;
-
                 let mut r = val_first * q1_i64;
                 r += val_rest * q2_i64;
 
                 // divide r by q2, rounding
                 let sign: i64 = if r >= 0 { 1 } else { -1 };
                 let mut res = ((r + sign * (denom / 2)) as i128) / (denom as i128);
-                res = (res + (denom as i128 / p_i128) * (p_i128) + 2 * (p_i128)) % (p_i128);
                 let idx = instance * params.n * params.n * params.poly_len + i;
+                noise.noise[idx] = (r as i128 - res * denom as i128).unsigned_abs() as u64;
+                res = (res + (denom as i128 / p_i128) * (p_i128) + 2 * (p_i128)) % (p_i128);
                 result.data[idx] = res as u64;
             }
         }
 
         // println!("{:?}", result.data.as_slice().to_vec());
-        result.to_vec(p_bits as usize, params.modp_words_per_chunk())
+        Ok((
+            result.to_vec(p_bits as usize, params.modp_words_per_chunk()),
+            noise,
+        ))
     }
 }
 
@@ -718,8 +1124,8 @@ impl<'a> Client<'a> {
 mod test {
     use super::*;
 
-    fn get_params() -> Params {
-        get_short_keygen_params()
+    fn get_params() -> Arc<Params> {
+        Arc::new(get_short_keygen_params())
     }
 
     #[test]
@@ -727,7 +1133,179 @@ mod test {
         let params = get_params();
         let client = Client::init(&params);
 
-        assert_eq!(*client.params, params);
+        assert_eq!(client.params, params);
+    }
+
+    #[test]
+    fn client_owns_params() {
+        let params = get_params();
+        let mut client = Client::init(&params);
+        drop(params);
+
+        let pub_params = client.generate_keys();
+        drop(client);
+
+        let params = pub_params.v_packing[0].get_params().clone();
+        drop(pub_params);
+        assert_eq!(Arc::strong_count(&params), 1);
+    }
+
+    #[test]
+    fn secret_key_export_is_correct() {
+        let params = get_params();
+        let mut client = Client::init(&params);
+        client.generate_secret_keys();
+        let exported = client.export_secret_key();
+        assert_eq!(exported.len(), params.secret_key_bytes());
+
+        let restored = Client::from_secret_key(&params, &exported).unwrap();
+        assert_eq!(restored.sk_gsw.as_slice(), client.sk_gsw.as_slice());
+        assert_eq!(
+            restored.sk_reg_full.as_slice(),
+            client.sk_reg_full.as_slice()
+        );
+        assert_eq!(restored.export_secret_key(), exported);
+
+        let mut other_params = get_params();
+        Arc::make_mut(&mut other_params).t_exp_right += 1;
+        assert!(matches!(
+            Client::from_secret_key(&other_params, &exported),
+            Err(SpiralError::WrongParams { .. })
+        ));
+
+        let mut bad_version = exported.clone();
+        bad_version[0] += 1;
+        assert!(matches!(
+            Client::from_secret_key(&params, &bad_version),
+            Err(SpiralError::UnsupportedVersion { .. })
+        ));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn locked_client_is_correct() {
+        let params = get_params();
+        let mut client = Client::init_locked(&params).unwrap();
+        client.generate_secret_keys();
+        assert!(client.sk_gsw.data.is_locked());
+        assert!(client.sk_reg_full.data.is_locked());
+        assert_eq!(
+            client.sk_gsw_full.get_poly(0, 0),
+            client.sk_gsw.get_poly(0, 0)
+        );
+        assert_eq!(client.sk_gsw_full.get_poly(0, 1)[0], 1);
+        assert!(client.sk_reg_ntt.data.is_locked());
+        assert!(client.decoder.sk_gsw_q2_ntt.data.is_locked());
+
+        let restored = Client::from_secret_key(&params, &client.export_secret_key()).unwrap();
+        assert!(!restored.sk_gsw.data.is_locked());
+        assert!(restored.sk_gsw.data.is_secret());
+        assert!(restored.sk_reg.ntt().data.is_secret());
+        assert!(!PolyMatrixRaw::zero(&params, 1, 1).ntt().data.is_secret());
+        assert_eq!(
+            restored.sk_reg_full.as_slice(),
+            client.sk_reg_full.as_slice()
+        );
+    }
+
+    #[test]
+    fn derived_keys_follow_secret_keys() {
+        let params = get_params();
+        let mut client = Client::init(&params);
+        for mut seed in [[1u8; SEED_LENGTH], [2u8; SEED_LENGTH]] {
+            client.generate_secret_keys_from_seed(&mut seed);
+            assert_eq!(client.sk_gsw_ntt.as_slice(), client.sk_gsw.ntt().as_slice());
+            assert_eq!(client.sk_reg_ntt.as_slice(), client.sk_reg.ntt().as_slice());
+
+            let q2_params = &client.decoder.q2_params;
+            let mut sk_gsw_q2 = PolyMatrixRaw::zero(q2_params, params.n, 1);
+            for i in 0..params.poly_len * params.n {
+                sk_gsw_q2.data[i] = recenter(client.sk_gsw.data[i], params.modulus, params.q2);
+            }
+            assert_eq!(
+                client.decoder.sk_gsw_q2_ntt.as_slice(),
+                sk_gsw_q2.ntt().as_slice()
+            );
+        }
+    }
+
+    #[test]
+    fn keygen_and_query_are_deterministic() {
+        for params in [get_params(), Arc::new(get_no_expansion_testing_params())] {
+            let run = |seed: Seed| {
+                let mut client = Client::init(&params);
+                let mut rng = ChaCha20Rng::from_seed(seed);
+                let pub_params = client.generate_keys_rng(&mut rng).serialize();
+                let query = client.generate_query_rng(1, &mut rng).unwrap().serialize();
+                (pub_params, query, client.export_secret_key())
+            };
+            assert!(run([1; 32]) == run([1; 32]));
+            let (pub_params, query, sk) = run([2; 32]);
+            assert!(run([1; 32]) != (pub_params.clone(), query, sk.clone()));
+
+            let mut client = Client::init(&params);
+            let mut seed = [2; 32];
+            assert!(client.generate_keys_from_seed(&mut seed).serialize() == pub_params);
+            assert_eq!(seed, Seed::default());
+            let mut seed = [2; 32];
+            client.generate_secret_keys_from_seed(&mut seed);
+            assert_eq!(seed, Seed::default());
+            assert!(client.export_secret_key() == sk);
+        }
+    }
+
+    #[test]
+    fn precomputed_queries_are_correct() {
+        for params in [get_params(), Arc::new(get_no_expansion_testing_params())] {
+            let mut client = Client::init(&params);
+            client.generate_secret_keys();
+
+            let seed = [1u8; SEED_LENGTH];
+            client.precompute_queries_rng(2, &mut ChaCha20Rng::from_seed(seed));
+            client.precompute_queries(3);
+            assert_eq!(client.num_precomputed_queries(), 3);
+
+            // finishing a precomputed query gives the query the same rng would have
+            let mut rng = ChaCha20Rng::from_seed(seed);
+            for idx_target in [1, 6] {
+                let expected = client
+                    .generate_query_rng(idx_target, &mut rng)
+                    .unwrap()
+                    .serialize();
+                let query = client
+                    .generate_query_from_pool(idx_target)
+                    .unwrap()
+                    .serialize();
+                assert_eq!(query, expected);
+            }
+            assert_eq!(client.num_precomputed_queries(), 1);
+
+            client.generate_secret_keys();
+            assert_eq!(client.num_precomputed_queries(), 0);
+            let query = client.generate_query_from_pool(0).unwrap();
+            assert_eq!(query.fingerprint, params.fingerprint());
+        }
+    }
+
+    #[test]
+    fn generate_query_rejects_out_of_range_index() {
+        let mut cfg = ParamsConfig::from(&*get_params());
+        cfg.num_items = Some(5);
+        let params = Arc::new(Params::try_from(cfg).unwrap());
+        let mut client = Client::init(&params);
+        client.generate_secret_keys();
+        client.precompute_queries(1);
+
+        assert!(client.generate_query(4).is_ok());
+        for idx_target in [5, params.num_slots(), usize::MAX] {
+            assert!(matches!(
+                client.generate_query(idx_target),
+                Err(SpiralError::IndexOutOfRange { num_items: 5, .. })
+            ));
+            assert!(client.generate_query_from_pool(idx_target).is_err());
+        }
+        assert_eq!(client.num_precomputed_queries(), 1);
+        assert!(client.generate_queries(&[0, 4, 5]).is_err());
     }
 
     #[test]
@@ -749,12 +1327,12 @@ mod test {
         v.iter().map(|d| d.as_slice().to_vec()).flatten().collect()
     }
 
-    fn public_parameters_serialization_is_correct_for_params(params: Params) {
+    fn public_parameters_serialization_is_correct_for_params(params: Arc<Params>) {
         let mut client = Client::init(&params);
         let pub_params = client.generate_keys();
 
         let serialized1 = pub_params.serialize();
-        let deserialized1 = PublicParameters::deserialize(&params, &serialized1);
+        let deserialized1 = PublicParameters::deserialize(&params, &serialized1).unwrap();
         let serialized2 = deserialized1.serialize();
 
         assert_eq!(serialized1, serialized2);
@@ -763,31 +1341,25 @@ mod test {
             get_vec(&deserialized1.v_packing)
         );
 
-        println!("packing mats (bytes) {}", get_vec(&pub_params.v_packing).len()*8);
+        println!(
+            "packing mats (bytes) {}",
+            get_vec(&pub_params.v_packing).len() * 8
+        );
         println!("total size   (bytes) {}", serialized1.len());
         if pub_params.v_conversion.is_some() {
             let l1 = get_vec(&pub_params.v_conversion.unwrap());
-            assert_eq!(
-                l1,
-                get_vec(&deserialized1.v_conversion.unwrap())
-            );
-            println!("conv mats (bytes) {}", l1.len()*8);
+            assert_eq!(l1, get_vec(&deserialized1.v_conversion.unwrap()));
+            println!("conv mats (bytes) {}", l1.len() * 8);
         }
         if pub_params.v_expansion_left.is_some() {
             let l1 = get_vec(&pub_params.v_expansion_left.unwrap());
-            assert_eq!(
-                l1,
-                get_vec(&deserialized1.v_expansion_left.unwrap())
-            );
-            println!("exp left (bytes) {}", l1.len()*8);
+            assert_eq!(l1, get_vec(&deserialized1.v_expansion_left.unwrap()));
+            println!("exp left (bytes) {}", l1.len() * 8);
         }
         if pub_params.v_expansion_right.is_some() {
             let l1 = get_vec(&pub_params.v_expansion_right.unwrap());
-            assert_eq!(
-                l1,
-                get_vec(&deserialized1.v_expansion_right.unwrap())
-            );
-            println!("exp right (bytes) {}", l1.len()*8);
+            assert_eq!(l1, get_vec(&deserialized1.v_expansion_right.unwrap()));
+            println!("exp right (bytes) {}", l1.len() * 8);
         }
     }
 
@@ -813,7 +1385,7 @@ mod test {
             'db_item_size': 100000 }
         "#;
         let cfg = cfg_expand.replace("'", "\"");
-        let params = params_from_json(&cfg);
+        let params = Arc::new(params_from_json(&cfg));
         public_parameters_serialization_is_correct_for_params(params)
     }
 
@@ -832,22 +1404,24 @@ mod test {
             "instances": 2,
             "db_item_size": 65536 }
         "#;
-        let params = params_from_json(&cfg);
+        let params = Arc::new(params_from_json(&cfg));
         public_parameters_serialization_is_correct_for_params(params)
     }
 
     #[test]
     fn no_expansion_public_parameters_serialization_is_correct() {
-        public_parameters_serialization_is_correct_for_params(get_no_expansion_testing_params())
+        public_parameters_serialization_is_correct_for_params(Arc::new(
+            get_no_expansion_testing_params(),
+        ))
     }
 
-    fn query_serialization_is_correct_for_params(params: Params) {
+    fn query_serialization_is_correct_for_params(params: Arc<Params>) {
         let mut client = Client::init(&params);
         _ = client.generate_keys();
-        let query = client.generate_query(1);
+        let query = client.generate_query(1).unwrap();
 
         let serialized1 = query.serialize();
-        let deserialized1 = Query::deserialize(&params, &serialized1);
+        let deserialized1 = Query::deserialize(&params, &serialized1).unwrap();
         let serialized2 = deserialized1.serialize();
 
         assert_eq!(serialized1.len(), serialized2.len());
@@ -863,6 +1437,67 @@ mod test {
 
     #[test]
     fn no_expansion_query_serialization_is_correct() {
-        query_serialization_is_correct_for_params(get_no_expansion_testing_params())
+        query_serialization_is_correct_for_params(Arc::new(get_no_expansion_testing_params()))
+    }
+
+    #[test]
+    fn setup_id_is_content_addressed() {
+        let params = get_params();
+        let pub_params = Client::init(&params).generate_keys();
+        let serialized = pub_params.serialize();
+
+        let id = setup_id(&serialized);
+        assert_eq!(id.len(), SETUP_ID_LENGTH);
+        assert_eq!(&id[14..15], "8");
+        assert_eq!(pub_params.setup_id(), id);
+        let deserialized = PublicParameters::deserialize(&params, &serialized).unwrap();
+        assert_eq!(deserialized.setup_id(), id);
+
+        let other = Client::init(&params).generate_keys();
+        assert_ne!(other.setup_id(), id);
+    }
+
+    #[test]
+    fn deserialize_rejects_malformed_data() {
+        let params = get_params();
+        let mut client = Client::init(&params);
+        let pub_params = client.generate_keys().serialize();
+        let query = client.generate_query(1).unwrap().serialize();
+
+        assert!(matches!(
+            Query::deserialize(&params, &query[..4]),
+            Err(SpiralError::Truncated { .. })
+        ));
+
+        assert!(matches!(
+            Query::deserialize(&params, &query[..query.len() - 8]),
+            Err(SpiralError::LengthMismatch { .. })
+        ));
+
+        let mut other_params = get_params();
+        Arc::make_mut(&mut other_params).t_exp_right += 1;
+        assert!(matches!(
+            PublicParameters::deserialize(&other_params, &pub_params),
+            Err(SpiralError::WrongParams { .. })
+        ));
+
+        let mut bad_pub_params = pub_params.clone();
+        let last = bad_pub_params.len() - 8;
+        bad_pub_params[last..].copy_from_slice(&u64::MAX.to_ne_bytes());
+        assert!(matches!(
+            PublicParameters::deserialize(&params, &bad_pub_params),
+            Err(SpiralError::CoefficientOutOfRange { .. })
+        ));
+
+        let mut bad_query = query.clone();
+        let last = bad_query.len() - 8;
+        bad_query[last..].copy_from_slice(&params.modulus.to_ne_bytes());
+        assert!(matches!(
+            Query::deserialize(&params, &bad_query),
+            Err(SpiralError::CoefficientOutOfRange { .. })
+        ));
+
+        let err = client.decode_response(&query).unwrap_err();
+        assert!(matches!(err, SpiralError::LengthMismatch { .. }));
     }
 }
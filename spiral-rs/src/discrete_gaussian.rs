@@ -1,48 +1,74 @@
-use rand::distributions::WeightedIndex;
-use rand::prelude::Distribution;
-use rand_chacha::ChaCha20Rng;
+use rand::RngCore;
 
 use crate::params::*;
 use crate::poly::*;
 use std::f64::consts::PI;
 
 pub const NUM_WIDTHS: usize = 8;
+// Table entries are probabilities scaled by 2^CDT_BITS, compared against CDT_BITS random bits
+pub const CDT_BITS: usize = 63;
 
 pub struct DiscreteGaussian {
-    choices: Vec<i64>,
-    dist: WeightedIndex<f64>,
+    // cdt[i] = 2^CDT_BITS * Pr[X <= i + min_val], without the leading 0s and trailing 1s
+    cdt: Vec<u64>,
+    min_val: i64,
 }
 
 impl DiscreteGaussian {
     pub fn init(params: &Params) -> Self {
-        let max_val = (params.noise_width * (NUM_WIDTHS as f64)).ceil() as i64;
-        let mut choices = Vec::new();
-        let mut table = vec![0f64; 0];
-        for i in -max_val..max_val + 1 {
-            let p_val = f64::exp(-PI * f64::powi(i as f64, 2) / f64::powi(params.noise_width, 2));
-            choices.push(i);
-            table.push(p_val);
+        Self::init_with_width(params.noise_width)
+    }
+
+    fn init_with_width(noise_width: f64) -> Self {
+        let max_val = (noise_width * (NUM_WIDTHS as f64)).ceil() as i64;
+        let weights: Vec<f64> = (-max_val..max_val + 1)
+            .map(|i| f64::exp(-PI * f64::powi(i as f64, 2) / f64::powi(noise_width, 2)))
+            .collect();
+        let total: f64 = weights.iter().sum();
+
+        // Rounding each probability on its own keeps the table symmetric; the rounding error
+        // goes to the center so that the probabilities sum to exactly 2^CDT_BITS.
+        let scale = (1u64 << CDT_BITS) as f64;
+        let mut probs: Vec<u64> = weights
+            .iter()
+            .map(|w| f64::round(w / total * scale) as u64)
+            .collect();
+        let sum: u64 = probs.iter().sum();
+        probs[max_val as usize] = (probs[max_val as usize] + (1 << CDT_BITS)) - sum;
+
+        // values that can never be drawn are left out of the table
+        let first = probs.iter().position(|&p| p != 0).unwrap();
+        let min_val = first as i64 - max_val;
+        let mut cdt = Vec::new();
+        let mut cumulative = 0;
+        for &p in probs[first..probs.len() - first - 1].iter() {
+            cumulative += p;
+            cdt.push(cumulative);
         }
-        let dist = WeightedIndex::new(&table).unwrap();
 
-        Self { choices, dist }
+        Self { cdt, min_val }
     }
 
-    // FIXME: not constant-time
-    pub fn sample(&self, rng: &mut ChaCha20Rng) -> i64 {
-        self.choices[self.dist.sample(rng)]
+    // Constant-time: every table entry is compared, and the comparisons are arithmetic
+    pub fn sample<R: RngCore>(&self, rng: &mut R) -> i64 {
+        let r = rng.next_u64() >> (64 - CDT_BITS);
+        let mut idx = 0;
+        for &c in self.cdt.iter() {
+            // 1 exactly when c <= r, since both are at most 2^CDT_BITS
+            idx += c.wrapping_sub(r).wrapping_sub(1) >> 63;
+        }
+        self.min_val + idx as i64
     }
 
-    pub fn sample_matrix(&self, p: &mut PolyMatrixRaw, rng: &mut ChaCha20Rng) {
+    pub fn sample_matrix<R: RngCore>(&self, p: &mut PolyMatrixRaw, rng: &mut R) {
         let modulus = p.get_params().modulus;
         for r in 0..p.rows {
             for c in 0..p.cols {
                 let poly = p.get_poly_mut(r, c);
                 for z in 0..poly.len() {
-                    let mut s = self.sample(rng);
-                    s += modulus as i64;
-                    s %= modulus as i64; // FIXME: not constant time
-                    poly[z] = s as u64;
+                    let s = self.sample(rng);
+                    // adds the modulus exactly when s is negative, without branching
+                    poly[z] = (s as u64).wrapping_add(modulus & (s >> 63) as u64);
                 }
             }
         }
@@ -53,10 +79,11 @@ impl DiscreteGaussian {
 mod test {
     use super::*;
     use crate::util::*;
+    use std::sync::Arc;
 
     #[test]
     fn dg_seems_okay() {
-        let params = get_test_params();
+        let params = Arc::new(get_test_params());
         let dg = DiscreteGaussian::init(&params);
         let mut rng = get_chacha_rng();
         let mut v = Vec::new();
@@ -72,4 +99,107 @@ mod test {
         let std_dev_of_mean = std_dev / f64::sqrt(trials as f64);
         assert!(f64::abs(mean) < std_dev_of_mean * 5f64);
     }
+
+    const TEST_WIDTHS: [f64; 2] = [3.2, DEFAULT_NOISE_WIDTH];
+
+    fn ideal_probability(noise_width: f64, val: i64) -> f64 {
+        let weight = |i: i64| f64::exp(-PI * f64::powi(i as f64, 2) / f64::powi(noise_width, 2));
+        let max_val = (noise_width * (NUM_WIDTHS as f64)).ceil() as i64;
+        weight(val) / (-max_val..max_val + 1).map(weight).sum::<f64>()
+    }
+
+    fn table_probability(dg: &DiscreteGaussian, val: i64) -> f64 {
+        let idx = val - dg.min_val;
+        if idx < 0 || idx > dg.cdt.len() as i64 {
+            return 0.;
+        }
+        let idx = idx as usize;
+        let hi = dg.cdt.get(idx).copied().unwrap_or(1 << CDT_BITS);
+        let lo = if idx == 0 { 0 } else { dg.cdt[idx - 1] };
+        (hi - lo) as f64 / (1u64 << CDT_BITS) as f64
+    }
+
+    #[test]
+    fn cdt_matches_ideal_distribution() {
+        for noise_width in TEST_WIDTHS {
+            let dg = DiscreteGaussian::init_with_width(noise_width);
+            let max_val = (noise_width * (NUM_WIDTHS as f64)).ceil() as i64;
+            assert!(dg.cdt.windows(2).all(|w| w[0] < w[1]));
+            assert_eq!(dg.min_val + dg.cdt.len() as i64, -dg.min_val);
+
+            let mut distance = 0.;
+            for val in -max_val..max_val + 1 {
+                distance +=
+                    f64::abs(table_probability(&dg, val) - ideal_probability(noise_width, val));
+            }
+            assert!(distance / 2. < f64::powi(2., -50), "width {}", noise_width);
+        }
+    }
+
+    #[test]
+    fn samples_match_ideal_distribution() {
+        let trials = 200000;
+        for noise_width in TEST_WIDTHS {
+            let dg = DiscreteGaussian::init_with_width(noise_width);
+            let mut rng = get_chacha_rng();
+            let max_val = -dg.min_val;
+            let mut counts = vec![0usize; 2 * max_val as usize + 1];
+            for _ in 0..trials {
+                counts[(dg.sample(&mut rng) + max_val) as usize] += 1;
+            }
+
+            // Pearson's chi-squared test, pooling values expected fewer than 5 times
+            let mut chi_sq = 0.;
+            let mut dof = 0;
+            let (mut pooled_count, mut pooled_expected) = (0., 0.);
+            for val in -max_val..max_val + 1 {
+                let count = counts[(val + max_val) as usize] as f64;
+                let expected = trials as f64 * ideal_probability(noise_width, val);
+                if expected < 5. {
+                    pooled_count += count;
+                    pooled_expected += expected;
+                    continue;
+                }
+                chi_sq += f64::powi(count - expected, 2) / expected;
+                dof += 1;
+            }
+            chi_sq += f64::powi(pooled_count - pooled_expected, 2) / pooled_expected;
+            // roughly the 1 - 10^-6 quantile of the chi-squared distribution
+            let dof = dof as f64;
+            assert!(
+                chi_sq < dof + 7. * f64::sqrt(2. * dof),
+                "width {}",
+                noise_width
+            );
+
+            let samples = counts
+                .iter()
+                .enumerate()
+                .map(|(i, &n)| (i as i64 - max_val, n));
+            let variance =
+                samples.map(|(v, n)| (v * v) as f64 * n as f64).sum::<f64>() / trials as f64;
+            let ideal_variance = f64::powi(noise_width, 2) / (2. * PI);
+            assert!(
+                f64::abs(variance / ideal_variance - 1.) < 0.02,
+                "width {}",
+                noise_width
+            );
+        }
+    }
+
+    #[test]
+    fn sample_matrix_reduces_correctly() {
+        let params = Arc::new(get_test_params());
+        let dg = DiscreteGaussian::init(&params);
+        let mut p = PolyMatrixRaw::zero(&params, 2, 2);
+        dg.sample_matrix(&mut p, &mut get_chacha_rng());
+
+        let bound = -dg.min_val as u64;
+        let modulus = params.modulus;
+        assert!(p
+            .as_slice()
+            .iter()
+            .all(|&x| x <= bound || x >= modulus - bound));
+        assert!(p.as_slice().iter().any(|&x| x > bound));
+    }
 }
@@ -0,0 +1,72 @@
+use std::fmt;
+
+use crate::params::ParamsError;
+
+#[derive(Debug)]
+pub enum SpiralError {
+    // Invalid or insecure parameters
+    Params(ParamsError),
+    // Data produced under a different parameter set
+    WrongParams { expected: u64, found: u64 },
+    // Data whose total size is not the one the parameters call for
+    LengthMismatch { expected: usize, found: usize },
+    // Data that ends before a field it should contain
+    Truncated { needed: usize, available: usize },
+    // A coefficient that is not reduced modulo the modulus it is encoded under
+    CoefficientOutOfRange { value: u64, modulus: u64 },
+    // An encoding version this build cannot read
+    UnsupportedVersion { version: u8 },
+    // A query for an item past the end of the database
+    IndexOutOfRange { index: usize, num_items: usize },
+}
+
+impl fmt::Display for SpiralError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpiralError::Params(e) => write!(f, "{}", e),
+            SpiralError::WrongParams { expected, found } => write!(
+                f,
+                "parameter mismatch: expected fingerprint {:016x}, got {:016x}",
+                expected, found
+            ),
+            SpiralError::LengthMismatch { expected, found } => {
+                write!(f, "expected {} bytes of data, got {}", expected, found)
+            }
+            SpiralError::Truncated { needed, available } => write!(
+                f,
+                "data is truncated: needed {} bytes, only {} available",
+                needed, available
+            ),
+            SpiralError::CoefficientOutOfRange { value, modulus } => {
+                write!(
+                    f,
+                    "coefficient {} is out of range for modulus {}",
+                    value, modulus
+                )
+            }
+            SpiralError::UnsupportedVersion { version } => {
+                write!(f, "unsupported encoding version {}", version)
+            }
+            SpiralError::IndexOutOfRange { index, num_items } => write!(
+                f,
+                "item index {} is out of range for {} items",
+                index, num_items
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SpiralError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SpiralError::Params(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ParamsError> for SpiralError {
+    fn from(e: ParamsError) -> Self {
+        SpiralError::Params(e)
+    }
+}
@@ -1,14 +1,19 @@
+use std::sync::Arc;
+
 use crate::{params::*, poly::*};
 
 pub fn get_bits_per(params: &Params, dim: usize) -> usize {
-    let modulus_log2 = params.modulus_log2;
+    bits_per_digit(params.modulus_log2, dim)
+}
+
+pub fn bits_per_digit(modulus_log2: u64, dim: usize) -> usize {
     if dim as u64 == modulus_log2 {
         return 1;
     }
     ((modulus_log2 as f64) / (dim as f64)).floor() as usize + 1
 }
 
-pub fn build_gadget(params: &Params, rows: usize, cols: usize) -> PolyMatrixRaw {
+pub fn build_gadget(params: &Arc<Params>, rows: usize, cols: usize) -> PolyMatrixRaw {
     let mut g = PolyMatrixRaw::zero(params, rows, cols);
     let nx = g.rows;
     let m = g.cols;
@@ -16,8 +21,8 @@ pub fn build_gadget(params: &Params, rows: usize, cols: usize) -> PolyMatrixRaw
     assert_eq!(m % nx, 0);
 
     let num_elems = m / nx;
-    let params = g.params;
-    let bits_per = get_bits_per(params, num_elems);
+    let params = g.params.clone();
+    let bits_per = get_bits_per(&params, num_elems);
 
     for i in 0..nx {
         for j in 0..num_elems {
@@ -31,13 +36,13 @@ pub fn build_gadget(params: &Params, rows: usize, cols: usize) -> PolyMatrixRaw
     g
 }
 
-pub fn gadget_invert_rdim<'a>(out: &mut PolyMatrixRaw<'a>, inp: &PolyMatrixRaw<'a>, rdim: usize) {
+pub fn gadget_invert_rdim(out: &mut PolyMatrixRaw, inp: &PolyMatrixRaw, rdim: usize) {
     assert_eq!(out.cols, inp.cols);
 
-    let params = inp.params;
+    let params = inp.params.clone();
     let mx = out.rows;
     let num_elems = mx / rdim;
-    let bits_per = get_bits_per(params, num_elems);
+    let bits_per = get_bits_per(&params, num_elems);
     let mask = (1u64 << bits_per) - 1;
 
     for i in 0..inp.cols {
@@ -59,12 +64,12 @@ pub fn gadget_invert_rdim<'a>(out: &mut PolyMatrixRaw<'a>, inp: &PolyMatrixRaw<'
     }
 }
 
-pub fn gadget_invert<'a>(out: &mut PolyMatrixRaw<'a>, inp: &PolyMatrixRaw<'a>) {
+pub fn gadget_invert(out: &mut PolyMatrixRaw, inp: &PolyMatrixRaw) {
     gadget_invert_rdim(out, inp, inp.rows);
 }
 
-pub fn gadget_invert_alloc<'a>(mx: usize, inp: &PolyMatrixRaw<'a>) -> PolyMatrixRaw<'a> {
-    let mut out = PolyMatrixRaw::zero(inp.params, mx, inp.cols);
+pub fn gadget_invert_alloc(mx: usize, inp: &PolyMatrixRaw) -> PolyMatrixRaw {
+    let mut out = PolyMatrixRaw::zero(&inp.params, mx, inp.cols);
     gadget_invert(&mut out, inp);
     out
 }
@@ -77,7 +82,7 @@ mod test {
 
     #[test]
     fn gadget_invert_is_correct() {
-        let params = get_test_params();
+        let params = Arc::new(get_test_params());
         let mut mat = PolyMatrixRaw::zero(&params, 2, 1);
         mat.get_poly_mut(0, 0)[37] = 3;
         mat.get_poly_mut(1, 0)[37] = 6;
@@ -0,0 +1,294 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{arith::*, client::*, params::*, server::*, util::*};
+
+pub const KAT_SEED: Seed = [0x5a; SEED_LENGTH];
+
+// A known-answer test: everything a client and server derive from `seed` under `params`.
+// Byte strings are hex. Public parameters run to megabytes, so they are kept raw in
+// `public_params_file`, next to the JSON, with their length and 64-bit FNV-1a digest in the
+// JSON. `params` is the preset `shrunk_from` with the fields in `shrunk_fields` changed to
+// make the database small.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KnownAnswerTest {
+    pub name: String,
+    pub shrunk_from: String,
+    pub shrunk_fields: Vec<String>,
+    pub params: ParamsConfig,
+    pub seed: String,
+    pub idx_target: usize,
+    pub secret_key: String,
+    pub public_params_file: String,
+    pub public_params_len: usize,
+    pub public_params_fnv1a: String,
+    #[serde(skip)]
+    pub public_params: Vec<u8>,
+    pub query: String,
+    pub db: String,
+    pub response: String,
+    pub decoded_item: String,
+}
+
+pub struct KatPreset {
+    pub name: String,
+    pub shrunk_from: &'static str,
+    pub shrunk_fields: Vec<String>,
+    pub cfg: ParamsConfig,
+}
+
+// The presets with their database shrunk to the fewest items query expansion allows, each
+// 64 bytes per plaintext polynomial. The ciphertext parameters are left as they are.
+pub fn kat_presets() -> Vec<KatPreset> {
+    [
+        ("cfg_20_256", CFG_20_256),
+        ("cfg_16_100000", CFG_16_100000),
+        ("direct_upload", CFG_NO_EXPANSION_TESTING),
+    ]
+    .into_iter()
+    .map(|(base_name, base)| {
+        let base: ParamsConfig = serde_json::from_str(&base.replace("'", "\"")).unwrap();
+        let mut cfg = base.clone();
+        // expansion produces the second dimension's bits in the odd slots of the first's
+        cfg.nu_2 = 1;
+        cfg.nu_1 = usize::max(2, log2_ceil_usize(cfg.t_gsw * cfg.nu_2));
+        cfg.instances = 1;
+        cfg.db_item_size = 64 * cfg.n * cfg.n;
+
+        let base = serde_json::to_value(&base).unwrap();
+        let shrunk = serde_json::to_value(&cfg).unwrap();
+        let shrunk_fields = shrunk
+            .as_object()
+            .unwrap()
+            .iter()
+            .filter(|(field, value)| base.get(field.as_str()) != Some(*value))
+            .map(|(field, _)| field.clone())
+            .collect();
+        KatPreset {
+            name: format!("{}_small", base_name),
+            shrunk_from: base_name,
+            shrunk_fields,
+            cfg,
+        }
+    })
+    .collect()
+}
+
+// The client draws its keys and then its query from one rng seeded with `seed`; the
+// database comes from stream 1 of the same seed.
+pub fn generate_kat(
+    preset: &KatPreset,
+    seed: Seed,
+    idx_target: usize,
+) -> Result<KnownAnswerTest, ParamsError> {
+    let params = Arc::new(Params::try_from(preset.cfg.clone())?);
+
+    let mut rng = ChaCha20Rng::from_seed(seed);
+    let mut client = Client::init(&params);
+    let pub_params = client.generate_keys_rng(&mut rng).serialize();
+    let query = client
+        .generate_query_rng(idx_target, &mut rng)
+        .unwrap()
+        .serialize();
+
+    let mut db_rng = ChaCha20Rng::from_seed(seed);
+    db_rng.set_stream(1);
+    let num_items = 1 << (params.db_dim_1 + params.db_dim_2);
+    let mut db = vec![0u8; num_items * params.db_item_size];
+    db_rng.fill_bytes(&mut db);
+
+    let response = answer_query(&params, &pub_params, &query, &db).unwrap();
+    let decoded_item = client.decode_response(&response).unwrap();
+
+    Ok(KnownAnswerTest {
+        name: preset.name.clone(),
+        shrunk_from: preset.shrunk_from.to_string(),
+        shrunk_fields: preset.shrunk_fields.clone(),
+        params: preset.cfg.clone(),
+        seed: to_hex(&seed),
+        idx_target,
+        secret_key: to_hex(&client.export_secret_key()),
+        public_params_file: format!("{}.public_params.bin", preset.name),
+        public_params_len: pub_params.len(),
+        public_params_fnv1a: format!("{:016x}", fnv1a(pub_params.iter().copied())),
+        public_params: pub_params,
+        query: to_hex(&query),
+        db: to_hex(&db),
+        response: to_hex(&response),
+        decoded_item: to_hex(&decoded_item),
+    })
+}
+
+// Writes `kat` into `dir` as `<name>.json`, with its public parameters beside it
+pub fn write_kat(dir: &Path, kat: &KnownAnswerTest) -> io::Result<PathBuf> {
+    fs::write(dir.join(&kat.public_params_file), &kat.public_params)?;
+    let path = dir.join(format!("{}.json", kat.name));
+    fs::write(&path, serde_json::to_string_pretty(kat)? + "\n")?;
+    Ok(path)
+}
+
+// Reads a vector written by `write_kat`, along with its public parameters
+pub fn read_kat(path: &Path) -> io::Result<KnownAnswerTest> {
+    let mut kat: KnownAnswerTest = serde_json::from_slice(&fs::read(path)?)?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    kat.public_params = fs::read(dir.join(&kat.public_params_file))?;
+    Ok(kat)
+}
+
+fn answer_query(
+    params: &Arc<Params>,
+    pub_params: &[u8],
+    query: &[u8],
+    db: &[u8],
+) -> Result<Vec<u8>, String> {
+    let pub_params =
+        PublicParameters::deserialize(params, pub_params).map_err(|e| e.to_string())?;
+    let query = Query::deserialize(params, query).map_err(|e| e.to_string())?;
+    let db = load_db_from_bytes(params, db);
+    Ok(process_query(params, &pub_params, &query, db.as_slice()))
+}
+
+// Replays a vector, checking each stage on its own inputs so that a mismatch points at the
+// stage that changed. Returns the name of the first field that does not match.
+pub fn check_kat(kat: &KnownAnswerTest) -> Result<(), String> {
+    let params = Arc::new(Params::try_from(kat.params.clone()).map_err(|e| e.to_string())?);
+    let seed: Seed = from_hex(&kat.seed)?
+        .try_into()
+        .map_err(|_| "seed".to_string())?;
+    let mismatch = |field: &str| Err(format!("{}: mismatch in {}", kat.name, field));
+
+    if kat.public_params.len() != kat.public_params_len
+        || format!("{:016x}", fnv1a(kat.public_params.iter().copied())) != kat.public_params_fnv1a
+    {
+        return mismatch("public_params_file");
+    }
+
+    // client: keys and query from the seed
+    let mut rng = ChaCha20Rng::from_seed(seed);
+    let mut client = Client::init(&params);
+    let pub_params = client.generate_keys_rng(&mut rng).serialize();
+    if to_hex(&client.export_secret_key()) != kat.secret_key {
+        return mismatch("secret_key");
+    }
+    if pub_params != kat.public_params {
+        return mismatch("public_params");
+    }
+    let query = client
+        .generate_query_rng(kat.idx_target, &mut rng)
+        .map_err(|e| e.to_string())?
+        .serialize();
+    if to_hex(&query) != kat.query {
+        return mismatch("query");
+    }
+
+    // server: the recorded query and public parameters against the recorded database
+    let response = answer_query(
+        &params,
+        &kat.public_params,
+        &from_hex(&kat.query)?,
+        &from_hex(&kat.db)?,
+    )?;
+    if to_hex(&response) != kat.response {
+        return mismatch("response");
+    }
+
+    // decoding: the recorded response with the recorded secret key
+    let client =
+        Client::from_secret_key(&params, &from_hex(&kat.secret_key)?).map_err(|e| e.to_string())?;
+    let decoded_item = client
+        .decode_response(&from_hex(&kat.response)?)
+        .map_err(|e| e.to_string())?;
+    if to_hex(&decoded_item) != kat.decoded_item {
+        return mismatch("decoded_item");
+    }
+    Ok(())
+}
+
+pub fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+pub fn from_hex(s: &str) -> Result<Vec<u8>, String> {
+    if !s.is_ascii() {
+        return Err("non-ASCII character in hex string".to_string());
+    }
+    if s.len() % 2 == 1 {
+        return Err(format!("odd-length hex string of {} digits", s.len()));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|e| e.to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const VECTORS: [&str; 3] = [
+        "cfg_20_256_small.json",
+        "cfg_16_100000_small.json",
+        "direct_upload_small.json",
+    ];
+
+    fn read_vector(name: &str) -> KnownAnswerTest {
+        read_kat(&Path::new(env!("CARGO_MANIFEST_DIR")).join("kat").join(name)).unwrap()
+    }
+
+    #[test]
+    fn committed_vectors_replay() {
+        for vector in VECTORS {
+            check_kat(&read_vector(vector)).unwrap();
+        }
+    }
+
+    #[test]
+    fn check_kat_detects_mismatches() {
+        let mut kat = read_vector(VECTORS[0]);
+        let original = kat.clone();
+        let last = kat.response.len() - 1;
+        let flipped = if kat.response.ends_with('0') {
+            "1"
+        } else {
+            "0"
+        };
+        kat.response.replace_range(last.., flipped);
+        assert!(check_kat(&kat).unwrap_err().contains("response"));
+
+        let mut kat = original.clone();
+        kat.public_params[0] ^= 1;
+        assert!(check_kat(&kat).unwrap_err().contains("public_params_file"));
+
+        // a consistent side file that differs from what the client derives
+        kat.public_params_fnv1a = format!("{:016x}", fnv1a(kat.public_params.iter().copied()));
+        assert!(check_kat(&kat).unwrap_err().ends_with("public_params"));
+    }
+
+    #[test]
+    fn write_kat_round_trips() {
+        let kat = read_vector(VECTORS[0]);
+        let dir = std::env::temp_dir().join(format!("spiral_kat_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = write_kat(&dir, &kat).unwrap();
+        assert_eq!(read_kat(&path).unwrap(), kat);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hex_is_correct() {
+        let data = vec![0x00, 0x01, 0xab, 0xff];
+        assert_eq!(to_hex(&data), "0001abff");
+        assert_eq!(from_hex("0001abff").unwrap(), data);
+        assert!(from_hex("abc").is_err());
+        assert!(from_hex("zz").is_err());
+        assert!(from_hex("éa").is_err());
+        assert!(from_hex("aé").is_err());
+    }
+}
@@ -1,6 +1,7 @@
 pub mod aligned_memory;
 pub mod arith;
 pub mod discrete_gaussian;
+pub mod error;
 pub mod number_theory;
 pub mod util;
 
@@ -8,7 +9,12 @@ pub mod gadget;
 pub mod ntt;
 pub mod params;
 pub mod noise_model;
+pub mod security;
 pub mod poly;
 
 pub mod client;
 pub mod server;
+pub mod cache;
+pub mod store;
+
+pub mod kat;
@@ -1,8 +1,23 @@
-use crate::params::*;
-use std::f64::consts::*;
+use crate::{arith::*, params::*, util::*};
+use std::{cmp::Ordering, f64::consts::*};
 
 const STD_DEV_BOUND: f64 = 5f64;
 
+pub const DEFAULT_P_ERR_LOG2: f64 = -40f64;
+
+const MAX_N: usize = 5;
+const MIN_P_BITS: usize = 2;
+const MAX_P_BITS: usize = 16;
+const T_GSW_CHOICES: [usize; 8] = [2, 3, 4, 5, 6, 8, 10, 12];
+const T_CONV_CHOICES: [usize; 6] = [2, 3, 4, 5, 6, 8];
+const T_EXP_LEFT_CHOICES: [usize; 6] = [4, 8, 12, 16, 28, 56];
+const T_EXP_RIGHT_CHOICES: [usize; 4] = [8, 16, 28, 56];
+
+// Rough cloud prices used to trade server work against communication:
+// $0.09 per GB transferred, $0.04 per core-hour at ~10^9 coefficient operations per second
+const COST_PER_BYTE: f64 = 0.09 / (1u64 << 30) as f64;
+const COST_PER_OP: f64 = 0.04 / 3600. / 1e9;
+
 // This a simplified subset of a Params instance
 pub struct Paramset {
     pub n: usize,
@@ -103,6 +118,197 @@ pub fn get_p_err(s: &Paramset, s_e: f64, q_prime: u64) -> f64 {
     p_err
 }
 
+// Relative number of coefficient operations the server performs per query
+pub fn estimate_server_cost(s: &Paramset, instances: usize) -> f64 {
+    let d = s.d as f64;
+    let ntt_factor = 2. + d.log2();
+    let trials = (instances * s.n * s.n) as f64;
+    let dim0 = 2f64.powi(s.db_dim_1 as i32);
+    let num_per = 2f64.powi(s.db_dim_2 as i32);
+
+    // first dimension: one multiply-add per database coefficient and ciphertext row
+    let first_dim = trials * dim0 * num_per * d * 2.;
+    // folding: gadget inversion and a 2 x 2*t_gsw product per ciphertext
+    let folding = trials * num_per * (2 * s.t_gsw) as f64 * d * ntt_factor;
+    // packing: one t_conv key switch per ciphertext
+    let packing = trials * ((s.n + 1) * s.t_conv) as f64 * d * ntt_factor;
+
+    let mut expansion = 0.;
+    if s.expand_queries {
+        let num_bits_to_gen = s.t_gsw * s.db_dim_2 + (1 << s.db_dim_1);
+        let t_exp = usize::max(s.t_exp_left, s.t_exp_right) as f64;
+        expansion = (2 * num_bits_to_gen) as f64 * t_exp * d * ntt_factor;
+        expansion += (s.t_gsw * s.db_dim_2 * 2 * s.t_conv) as f64 * d * ntt_factor;
+    }
+
+    first_dim + folding + packing + expansion
+}
+
+pub struct ParamsCandidate {
+    pub config: ParamsConfig,
+    pub p_err_log2: f64,
+    pub setup_bytes: usize,
+    pub query_bytes: usize,
+    pub response_bytes: usize,
+    pub server_cost: f64,
+}
+
+impl ParamsCandidate {
+    // Estimated price of one query; the one-time setup upload is not included
+    pub fn cost(&self) -> f64 {
+        let comm = (self.query_bytes + self.response_bytes) as f64;
+        comm * COST_PER_BYTE + self.server_cost * COST_PER_OP
+    }
+
+    fn cmp_rank(&self, other: &Self) -> Ordering {
+        self.cost()
+            .partial_cmp(&other.cost())
+            .unwrap()
+            .then(self.setup_bytes.cmp(&other.setup_bytes))
+    }
+}
+
+fn get_instances(item_size: usize, n: usize, p_bits: usize, poly_len: usize) -> usize {
+    let bits_per_instance = n * n * poly_len * p_bits;
+    let mut instances = usize::max(1, (item_size * 8) / bits_per_instance);
+    loop {
+        let chunks = instances * n * n;
+        let bytes_per_chunk = f64::ceil(item_size as f64 / chunks as f64) as usize;
+        let modp_words_per_chunk = f64::ceil((bytes_per_chunk * 8) as f64 / p_bits as f64) as usize;
+        if modp_words_per_chunk <= poly_len {
+            return instances;
+        }
+        instances += 1;
+    }
+}
+
+fn get_min_q2_bits(
+    s: &Paramset,
+    s_e: f64,
+    max_p_err_log2: f64,
+    q2_values: &[(u64, u64)],
+) -> Option<(u64, f64)> {
+    q2_values
+        .iter()
+        .take_while(|&&(_, q2)| q2 < s.q)
+        .map(|&(q2_bits, q2)| (q2_bits, get_p_err(s, s_e, q2)))
+        .find(|&(_, p_err)| p_err <= max_p_err_log2)
+}
+
+// Enumerates expansion parameter sets that hold `num_items` items of `item_size` bytes with a
+// failure probability of at most 2^max_p_err_log2, best first.
+pub fn search_params(
+    num_items: usize,
+    item_size: usize,
+    max_p_err_log2: f64,
+) -> Vec<ParamsCandidate> {
+    let d = DEFAULT_POLY_LEN;
+    let d_log2 = log2(d as u64) as usize;
+    let q: u64 = get_default_moduli(d).iter().product();
+    let total_dims = usize::max(log2_ceil_usize(num_items), 1);
+    let q2_values: Vec<(u64, u64)> = (MIN_Q2_BITS..=MAX_MODULUS_BITS)
+        .filter_map(|q2_bits| Some((q2_bits, get_q2(q2_bits, d)?)))
+        .collect();
+
+    let mut candidates = Vec::new();
+    for n in 1..=MAX_N {
+        for p_bits in MIN_P_BITS..=MAX_P_BITS {
+            let instances = get_instances(item_size, n, p_bits, d);
+            for db_dim_1 in 1..=total_dims {
+                let db_dim_2 = total_dims - db_dim_1;
+                for t_gsw in T_GSW_CHOICES {
+                    if log2_ceil_usize(t_gsw * db_dim_2 + (1 << db_dim_1)) > d_log2 {
+                        continue;
+                    }
+                    for t_conv in T_CONV_CHOICES {
+                        for t_exp_left in T_EXP_LEFT_CHOICES {
+                            for t_exp_right in T_EXP_RIGHT_CHOICES {
+                                let s = Paramset {
+                                    n,
+                                    d,
+                                    p: 1 << p_bits,
+                                    q,
+                                    sigma: DEFAULT_NOISE_WIDTH,
+                                    t_conv,
+                                    t_exp_left,
+                                    t_exp_right,
+                                    t_gsw,
+                                    db_dim_1,
+                                    db_dim_2,
+                                    expand_queries: true,
+                                };
+                                let s_e = get_noise_from_paramset(&s);
+                                let (q2_bits, p_err_log2) =
+                                    match get_min_q2_bits(&s, s_e, max_p_err_log2, &q2_values) {
+                                        Some(x) => x,
+                                        None => continue,
+                                    };
+
+                                let config = ParamsConfig {
+                                    n,
+                                    nu_1: db_dim_1,
+                                    nu_2: db_dim_2,
+                                    p: s.p,
+                                    q2_bits,
+                                    t_gsw,
+                                    t_conv,
+                                    t_exp_left,
+                                    t_exp_right,
+                                    instances,
+                                    db_item_size: item_size,
+                                    num_items: Some(num_items),
+                                    direct_upload: None,
+                                    poly_len: d,
+                                    moduli: Vec::new(),
+                                    noise_width: DEFAULT_NOISE_WIDTH,
+                                    s_e: Some(f64::log2(s_e)),
+                                };
+                                let sizing = sizing_params(&config);
+                                candidates.push(ParamsCandidate {
+                                    config,
+                                    p_err_log2,
+                                    setup_bytes: sizing.setup_bytes(),
+                                    query_bytes: sizing.query_bytes(),
+                                    response_bytes: sizing.response_bytes(),
+                                    server_cost: estimate_server_cost(&s, instances),
+                                });
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    candidates.sort_by(|a, b| a.cmp_rank(b));
+    candidates
+}
+
+pub fn select_params(num_items: usize, item_size: usize, max_p_err_log2: f64) -> Option<Params> {
+    search_params(num_items, item_size, max_p_err_log2)
+        .into_iter()
+        .find_map(|c| Params::try_from(c.config).ok())
+}
+
+// Only the scalar fields, which is all the size computations need
+fn sizing_params(cfg: &ParamsConfig) -> Params {
+    Params {
+        poly_len: DEFAULT_POLY_LEN,
+        n: cfg.n,
+        pt_modulus: cfg.p,
+        q2_bits: cfg.q2_bits,
+        t_conv: cfg.t_conv,
+        t_exp_left: cfg.t_exp_left,
+        t_exp_right: cfg.t_exp_right,
+        t_gsw: cfg.t_gsw,
+        expand_queries: cfg.direct_upload.is_none(),
+        db_dim_1: cfg.nu_1,
+        db_dim_2: cfg.nu_2,
+        instances: cfg.instances,
+        db_item_size: cfg.db_item_size,
+        ..get_empty_params()
+    }
+}
 
 #[cfg(test)]
 mod test {
@@ -137,4 +343,41 @@ mod test {
         let p_err_bad = get_p_err(&paramset, s_e, 1 << (params.q2_bits - 1));
         assert!(p_err_bad > -40.0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn search_params_is_correct() {
+        let num_items = 1 << 14;
+        let item_size = 10000;
+        let candidates = search_params(num_items, item_size, DEFAULT_P_ERR_LOG2);
+        assert!(candidates.len() > 0);
+
+        for c in candidates.iter() {
+            assert!(c.p_err_log2 <= DEFAULT_P_ERR_LOG2);
+        }
+        for pair in candidates.windows(2) {
+            assert_ne!(pair[0].cmp_rank(&pair[1]), Ordering::Greater);
+        }
+
+        let params = select_params(num_items, item_size, DEFAULT_P_ERR_LOG2).unwrap();
+        assert_eq!(params.num_items(), num_items);
+        assert!(params.num_slots() >= num_items);
+        assert_eq!(params.db_item_size, item_size);
+        assert!(params.modp_words_per_chunk() <= params.poly_len);
+
+        let paramset = extract_paramset(&params);
+        let s_e = get_noise_from_paramset(&paramset);
+        assert!(get_p_err(&paramset, s_e, params.q2) <= DEFAULT_P_ERR_LOG2);
+    }
+
+    #[test]
+    fn search_params_beats_hand_picked() {
+        let hand_picked = params_from_json(&CFG_16_100000.replace("'", "\""));
+        let hand_picked_comm = hand_picked.query_bytes() + hand_picked.response_bytes();
+        let hand_picked_cost = hand_picked_comm as f64 * COST_PER_BYTE
+            + estimate_server_cost(&extract_paramset(&hand_picked), hand_picked.instances)
+                * COST_PER_OP;
+
+        let best = &search_params(1 << 16, 100000, DEFAULT_P_ERR_LOG2)[0];
+        assert!(best.cost() <= hand_picked_cost);
+    }
+}
@@ -41,18 +41,27 @@ pub fn build_ntt_tables(poly_len: usize, moduli: &[u64]) -> Vec<Vec<Vec<u64>>> {
     let mut output: Vec<Vec<Vec<u64>>> = vec![Vec::new(); moduli.len()];
     for coeff_mod in 0..moduli.len() {
         let modulus = moduli[coeff_mod];
-        let modulus_as_u32 = modulus.try_into().unwrap();
-        let root = get_minimal_primitive_root(2 * poly_len as u64, modulus).unwrap();
+        let root = get_ntt_root(modulus, poly_len).unwrap();
         let inv_root = invert_uint_mod(root, modulus).unwrap();
 
         let root_powers = powers_of_primitive_root(root, modulus, poly_len_log2);
-        let scaled_root_powers = scale_powers_u32(modulus_as_u32, poly_len, root_powers.as_slice());
         let mut inv_root_powers = powers_of_primitive_root(inv_root, modulus, poly_len_log2);
         for i in 0..poly_len {
             inv_root_powers[i] = div2_uint_mod(inv_root_powers[i], modulus);
         }
-        let scaled_inv_root_powers =
-            scale_powers_u32(modulus_as_u32, poly_len, inv_root_powers.as_slice());
+
+        let (scaled_root_powers, scaled_inv_root_powers) = if modulus < 1 << SMALL_MODULUS_BITS {
+            let modulus_as_u32 = modulus.try_into().unwrap();
+            (
+                scale_powers_u32(modulus_as_u32, poly_len, root_powers.as_slice()),
+                scale_powers_u32(modulus_as_u32, poly_len, inv_root_powers.as_slice()),
+            )
+        } else {
+            (
+                scale_powers_u64(modulus, poly_len, root_powers.as_slice()),
+                scale_powers_u64(modulus, poly_len, inv_root_powers.as_slice()),
+            )
+        };
 
         output[coeff_mod] = vec![
             root_powers,
@@ -64,6 +73,93 @@ pub fn build_ntt_tables(poly_len: usize, moduli: &[u64]) -> Vec<Vec<Vec<u64>>> {
     output
 }
 
+// Scalar NTT for a modulus too large for the 32-bit kernels, with 64-bit scaled powers.
+fn ntt_forward_u64(params: &Params, operand: &mut [u64], coeff_mod: usize) {
+    let log_n = params.poly_len_log2;
+    let n = 1 << log_n;
+
+    let forward_table = params.get_ntt_forward_table(coeff_mod);
+    let forward_table_prime = params.get_ntt_forward_prime_table(coeff_mod);
+    let modulus = params.moduli[coeff_mod];
+    let two_times_modulus = 2 * modulus;
+
+    for mm in 0..log_n {
+        let m = 1 << mm;
+        let t = n >> (mm + 1);
+
+        let mut it = operand.chunks_exact_mut(2 * t);
+
+        for i in 0..m {
+            let w = forward_table[m + i];
+            let w_prime = forward_table_prime[m + i];
+
+            let op = it.next().unwrap();
+
+            for j in 0..t {
+                let x = op[j];
+                let y = op[t + j];
+
+                let curr_x = x - (two_times_modulus * ((x >= two_times_modulus) as u64));
+                let q_tmp = (((y as u128) * (w_prime as u128)) >> 64) as u64;
+                let q_new = w.wrapping_mul(y).wrapping_sub(q_tmp.wrapping_mul(modulus));
+
+                op[j] = curr_x + q_new;
+                op[t + j] = curr_x + (two_times_modulus - q_new);
+            }
+        }
+    }
+
+    for x in operand.iter_mut() {
+        *x -= ((*x >= two_times_modulus) as u64) * two_times_modulus;
+        *x -= ((*x >= modulus) as u64) * modulus;
+    }
+}
+
+fn ntt_inverse_u64(params: &Params, operand: &mut [u64], coeff_mod: usize) {
+    let n = params.poly_len;
+
+    let inverse_table = params.get_ntt_inverse_table(coeff_mod);
+    let inverse_table_prime = params.get_ntt_inverse_prime_table(coeff_mod);
+    let modulus = params.moduli[coeff_mod];
+    let two_times_modulus = 2 * modulus;
+
+    for mm in (0..params.poly_len_log2).rev() {
+        let h = 1 << mm;
+        let t = n >> (mm + 1);
+
+        let mut it = operand.chunks_exact_mut(2 * t);
+
+        for i in 0..h {
+            let w = inverse_table[h + i];
+            let w_prime = inverse_table_prime[h + i];
+
+            let op = it.next().unwrap();
+
+            for j in 0..t {
+                let x = op[j];
+                let y = op[t + j];
+
+                let t_tmp = two_times_modulus - y + x;
+                let curr_x = x + y - (two_times_modulus * (((x << 1) >= t_tmp) as u64));
+                let h_tmp = (((t_tmp as u128) * (w_prime as u128)) >> 64) as u64;
+
+                let res_x = (curr_x + (modulus * (t_tmp & 1))) >> 1;
+                let res_y = w
+                    .wrapping_mul(t_tmp)
+                    .wrapping_sub(h_tmp.wrapping_mul(modulus));
+
+                op[j] = res_x;
+                op[t + j] = res_y;
+            }
+        }
+    }
+
+    for x in operand.iter_mut() {
+        *x -= ((*x >= two_times_modulus) as u64) * two_times_modulus;
+        *x -= ((*x >= modulus) as u64) * modulus;
+    }
+}
+
 #[cfg(not(target_feature = "avx2"))]
 pub fn ntt_forward(params: &Params, operand_overall: &mut [u64]) {
     let log_n = params.poly_len_log2;
@@ -71,6 +167,10 @@ pub fn ntt_forward(params: &Params, operand_overall: &mut [u64]) {
 
     for coeff_mod in 0..params.crt_count {
         let operand = &mut operand_overall[coeff_mod * n..coeff_mod * n + n];
+        if params.moduli[coeff_mod] >= 1 << SMALL_MODULUS_BITS {
+            ntt_forward_u64(params, operand, coeff_mod);
+            continue;
+        }
 
         let forward_table = params.get_ntt_forward_table(coeff_mod);
         let forward_table_prime = params.get_ntt_forward_prime_table(coeff_mod);
@@ -119,6 +219,10 @@ pub fn ntt_forward(params: &Params, operand_overall: &mut [u64]) {
 
     for coeff_mod in 0..params.crt_count {
         let operand = &mut operand_overall[coeff_mod * n..coeff_mod * n + n];
+        if params.moduli[coeff_mod] >= 1 << SMALL_MODULUS_BITS {
+            ntt_forward_u64(params, operand, coeff_mod);
+            continue;
+        }
 
         let forward_table = params.get_ntt_forward_table(coeff_mod);
         let forward_table_prime = params.get_ntt_forward_prime_table(coeff_mod);
@@ -193,14 +297,17 @@ pub fn ntt_forward(params: &Params, operand_overall: &mut [u64]) {
             unsafe {
                 let p_x = &mut operand[i] as *mut u64;
 
+                // x >= 2q and x >= q, so that outputs are fully reduced
                 let cmp_val1 = _mm256_set1_epi64x(two_times_modulus_small as i64);
+                let ge_val1 = _mm256_set1_epi64x(two_times_modulus_small as i64 - 1);
                 let mut x = _mm256_load_si256(p_x as *const __m256i);
-                let mut gt_mask = _mm256_cmpgt_epi64(x, cmp_val1);
+                let mut gt_mask = _mm256_cmpgt_epi64(x, ge_val1);
                 let mut to_subtract = _mm256_and_si256(gt_mask, cmp_val1);
                 x = _mm256_sub_epi64(x, to_subtract);
 
                 let cmp_val2 = _mm256_set1_epi64x(modulus_small as i64);
-                gt_mask = _mm256_cmpgt_epi64(x, cmp_val2);
+                let ge_val2 = _mm256_set1_epi64x(modulus_small as i64 - 1);
+                gt_mask = _mm256_cmpgt_epi64(x, ge_val2);
                 to_subtract = _mm256_and_si256(gt_mask, cmp_val2);
                 x = _mm256_sub_epi64(x, to_subtract);
                 _mm256_store_si256(p_x as *mut __m256i, x);
@@ -215,6 +322,10 @@ pub fn ntt_inverse(params: &Params, operand_overall: &mut [u64]) {
         let n = params.poly_len;
 
         let operand = &mut operand_overall[coeff_mod * n..coeff_mod * n + n];
+        if params.moduli[coeff_mod] >= 1 << SMALL_MODULUS_BITS {
+            ntt_inverse_u64(params, operand, coeff_mod);
+            continue;
+        }
 
         let inverse_table = params.get_ntt_inverse_table(coeff_mod);
         let inverse_table_prime = params.get_ntt_inverse_prime_table(coeff_mod);
@@ -263,6 +374,10 @@ pub fn ntt_inverse(params: &Params, operand_overall: &mut [u64]) {
         let n = params.poly_len;
 
         let operand = &mut operand_overall[coeff_mod * n..coeff_mod * n + n];
+        if params.moduli[coeff_mod] >= 1 << SMALL_MODULUS_BITS {
+            ntt_inverse_u64(params, operand, coeff_mod);
+            continue;
+        }
 
         let inverse_table = params.get_ntt_inverse_table(coeff_mod);
         let inverse_table_prime = params.get_ntt_inverse_prime_table(coeff_mod);
@@ -422,6 +537,26 @@ mod test {
         assert_eq!(v1[2048 + 50], 0);
     }
 
+    #[test]
+    fn ntt_forward_reduces_fully() {
+        let params = get_params();
+        let mut v1 = AlignedMemory64::new(params.crt_count * params.poly_len);
+        ntt_forward(&params, v1.as_mut_slice());
+        assert!(v1.as_slice().iter().all(|&x| x == 0));
+
+        let mut rng = rand::thread_rng();
+        for i in 0..params.crt_count {
+            for j in 0..params.poly_len {
+                v1[i * params.poly_len + j] = rng.gen::<u64>() % params.moduli[i];
+            }
+        }
+        ntt_forward(&params, v1.as_mut_slice());
+        for i in 0..params.crt_count {
+            let residues = &v1.as_slice()[i * params.poly_len..(i + 1) * params.poly_len];
+            assert!(residues.iter().all(|&x| x < params.moduli[i]));
+        }
+    }
+
     #[test]
     fn ntt_correct() {
         let params = get_params();
@@ -3,6 +3,57 @@ use rand::Rng;
 
 const ATTEMPT_MAX: usize = 100;
 
+// Bases that make Miller-Rabin deterministic for every n < 2^64.
+const MILLER_RABIN_BASES: [u64; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+
+pub fn is_prime(n: u64) -> bool {
+    if n < 2 {
+        return false;
+    }
+    for p in MILLER_RABIN_BASES {
+        if n.is_multiple_of(p) {
+            return n == p;
+        }
+    }
+
+    let s = (n - 1).trailing_zeros();
+    let d = (n - 1) >> s;
+    'witness: for a in MILLER_RABIN_BASES {
+        let mut x = exponentiate_uint_mod(a, d, n);
+        if x == 1 || x == n - 1 {
+            continue;
+        }
+        for _ in 1..s {
+            x = multiply_uint_mod(x, x, n);
+            if x == n - 1 {
+                continue 'witness;
+            }
+        }
+        return false;
+    }
+    true
+}
+
+// Returns up to `count` primes below 2^bits that are 1 mod 2 * poly_len, largest first.
+pub fn get_ntt_primes(bits: u64, poly_len: usize, count: usize) -> Vec<u64> {
+    assert!(bits < 64);
+    let degree = 2 * poly_len as u64;
+    let mut primes = Vec::with_capacity(count);
+    let mut candidate = ((1u64 << bits) - 1) / degree * degree + 1;
+    while primes.len() < count && candidate > degree {
+        if is_prime(candidate) {
+            primes.push(candidate);
+        }
+        candidate -= degree;
+    }
+    primes
+}
+
+// The smallest primitive 2 * poly_len-th root of unity mod `modulus`, as used by the NTT.
+pub fn get_ntt_root(modulus: u64, poly_len: usize) -> Option<u64> {
+    get_minimal_primitive_root(2 * poly_len as u64, modulus)
+}
+
 pub fn is_primitive_root(root: u64, degree: u64, modulus: u64) -> bool {
     if root == 0 {
         return false;
@@ -94,3 +145,58 @@ pub fn invert_uint_mod(value: u64, modulus: u64) -> Option<u64> {
         return Some(gcd_tuple.1 as u64);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn is_prime_is_correct() {
+        let small: Vec<u64> = (0..200).filter(|&n| is_prime(n)).collect();
+        let expected: Vec<u64> = (0..200)
+            .filter(|&n| n >= 2 && (2..n).all(|d| n % d != 0))
+            .collect();
+        assert_eq!(small, expected);
+
+        for n in [
+            268369921,
+            249561089,
+            2013265921,
+            72057594037616641,
+            18446744073709551557,
+        ] {
+            assert!(is_prime(n));
+        }
+        // strong pseudoprimes to several of the smaller bases, and a square of a prime
+        for n in [
+            3215031751,
+            3825123056546413051,
+            268369921 * 249561089,
+            4294967291 * 4294967291,
+        ] {
+            assert!(!is_prime(n));
+        }
+    }
+
+    #[test]
+    fn get_ntt_primes_is_correct() {
+        assert_eq!(get_ntt_primes(28, 2048, 1), vec![268369921]);
+        assert_eq!(get_ntt_primes(28, 4096, 2), vec![268369921, 268361729]);
+        assert_eq!(get_ntt_primes(20, 2048, 1), vec![1032193]);
+
+        for poly_len in [256, 2048, 4096] {
+            let primes = get_ntt_primes(56, poly_len, 3);
+            assert_eq!(primes.len(), 3);
+            for &q in &primes {
+                assert!(q < 1 << 56 && q > 1 << 55);
+                assert_eq!(q % (2 * poly_len as u64), 1);
+                let root = get_ntt_root(q, poly_len).unwrap();
+                assert!(is_primitive_root(root, 2 * poly_len as u64, q));
+            }
+            assert!(primes.windows(2).all(|w| w[0] > w[1]));
+        }
+
+        // no prime below 2^14 is 1 mod 2^14
+        assert!(get_ntt_primes(14, 8192, 1).is_empty());
+    }
+}
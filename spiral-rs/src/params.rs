@@ -1,49 +1,15 @@
-use std::mem::size_of;
+use std::{fmt, mem::size_of, sync::Arc};
 
-use crate::{arith::*, client::SEED_LENGTH, ntt::*, number_theory::*, poly::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    arith::*, client::SEED_LENGTH, gadget::*, ntt::*, number_theory::*, poly::*, util::fnv1a,
+};
 
 pub const MAX_MODULI: usize = 4;
+pub const FINGERPRINT_LENGTH: usize = 8;
 
 pub static MIN_Q2_BITS: u64 = 14;
-pub static Q2_VALUES: [u64; 37] = [
-    0,
-    0,
-    0,
-    0,
-    0,
-    0,
-    0,
-    0,
-    0,
-    0,
-    0,
-    0,
-    0,
-    0,
-    12289,
-    12289,
-    61441,
-    65537,
-    65537,
-    520193,
-    786433,
-    786433,
-    3604481,
-    7340033,
-    16515073,
-    33292289,
-    67043329,
-    132120577,
-    268369921,
-    469762049,
-    1073479681,
-    2013265921,
-    4293918721,
-    8588886017,
-    17175674881,
-    34359214081,
-    68718428161,
-];
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Params {
@@ -59,6 +25,7 @@ pub struct Params {
     pub barrett_cr_1_modulus: u64,
     pub mod0_inv_mod1: u64,
     pub mod1_inv_mod0: u64,
+    pub crt_coeffs: [u64; MAX_MODULI],
     pub moduli: [u64; MAX_MODULI],
     pub modulus: u64,
     pub modulus_log2: u64,
@@ -67,6 +34,7 @@ pub struct Params {
     pub n: usize,
     pub pt_modulus: u64,
     pub q2_bits: u64,
+    pub q2: u64,
     pub t_conv: usize,
     pub t_exp_left: usize,
     pub t_exp_right: usize,
@@ -77,6 +45,8 @@ pub struct Params {
     pub db_dim_2: usize,
     pub instances: usize,
     pub db_item_size: usize,
+    // Items actually in the database; the slots after them are padding
+    pub db_num_items: usize,
 }
 
 impl Params {
@@ -93,11 +63,11 @@ impl Params {
         self.ntt_tables[i][3].as_slice()
     }
 
-    pub fn get_v_neg1(&self) -> Vec<PolyMatrixNTT> {
+    pub fn get_v_neg1(self: &Arc<Self>) -> Vec<PolyMatrixNTT> {
         let mut v_neg1 = Vec::new();
         for i in 0..self.poly_len_log2 {
             let idx = self.poly_len - (1 << i);
-            let mut ng1 = PolyMatrixRaw::zero(&self, 1, 1);
+            let mut ng1 = PolyMatrixRaw::zero(self, 1, 1);
             ng1.data[idx] = 1;
             v_neg1.push((-&ng1).ntt());
         }
@@ -116,6 +86,10 @@ impl Params {
     }
 
     pub fn num_items(&self) -> usize {
+        self.db_num_items
+    }
+
+    pub fn num_slots(&self) -> usize {
         (1 << self.db_dim_1) * (1 << self.db_dim_2)
     }
 
@@ -156,7 +130,7 @@ impl Params {
         }
 
         let sz_bytes = sz_polys * self.poly_len * size_of::<u64>();
-        SEED_LENGTH + sz_bytes
+        FINGERPRINT_LENGTH + SEED_LENGTH + sz_bytes
     }
 
     pub fn query_bytes(&self) -> usize {
@@ -171,7 +145,27 @@ impl Params {
         }
 
         let sz_bytes = sz_polys * self.poly_len * size_of::<u64>();
-        SEED_LENGTH + sz_bytes
+        FINGERPRINT_LENGTH + SEED_LENGTH + sz_bytes
+    }
+
+    pub fn secret_key_bytes(&self) -> usize {
+        let (gsw_rows, gsw_cols) = self.get_sk_gsw();
+        let (reg_rows, reg_cols) = self.get_sk_reg();
+        let sz_polys = gsw_rows * gsw_cols + reg_rows * reg_cols;
+
+        let sz_bytes = sz_polys * self.poly_len * size_of::<u64>();
+        // one version byte precedes the fingerprint
+        1 + FINGERPRINT_LENGTH + sz_bytes
+    }
+
+    pub fn response_bytes(&self) -> usize {
+        let q1_bits = log2_ceil(4 * self.pt_modulus) as usize;
+        let q2_bits = self.q2_bits as usize;
+
+        let num_bits = self.instances
+            * ((q2_bits * self.n * self.poly_len) + (q1_bits * self.n * self.n * self.poly_len));
+        let num_words = f64::ceil(num_bits as f64 / 64f64) as usize;
+        FINGERPRINT_LENGTH + num_words * size_of::<u64>()
     }
 
     pub fn query_v_buf_bytes(&self) -> usize {
@@ -192,6 +186,34 @@ impl Params {
         modp_words_per_chunk
     }
 
+    // FNV-1a over the fields that determine the wire formats; must stay stable across releases
+    pub fn fingerprint(&self) -> u64 {
+        let mut fields = vec![self.poly_len as u64, self.crt_count as u64];
+        fields.extend_from_slice(&self.moduli[..self.crt_count]);
+        fields.extend_from_slice(&[
+            self.n as u64,
+            self.pt_modulus,
+            self.q2_bits,
+            self.q2,
+            self.t_conv as u64,
+            self.t_exp_left as u64,
+            self.t_exp_right as u64,
+            self.t_gsw as u64,
+            self.expand_queries as u64,
+            self.db_dim_1 as u64,
+            self.db_dim_2 as u64,
+            self.instances as u64,
+            self.db_item_size as u64,
+            self.db_num_items as u64,
+        ]);
+
+        fnv1a(fields.iter().flat_map(|x| x.to_le_bytes()))
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&ParamsConfig::from(self)).unwrap()
+    }
+
     pub fn crt_compose_1(&self, x: u64) -> u64 {
         assert_eq!(self.crt_count, 1);
         x
@@ -206,14 +228,41 @@ impl Params {
         barrett_reduction_u128(self, val)
     }
 
+    pub fn crt_compose_n(&self, a: &[u64], idx: usize) -> u64 {
+        // each term is below 2^124, so up to MAX_MODULI of them fit in a u128
+        let mut val = 0u128;
+        for n in 0..self.crt_count {
+            val += (a[idx + n * self.poly_len] as u128) * (self.crt_coeffs[n] as u128);
+        }
+
+        barrett_reduction_u128(self, val)
+    }
+
     pub fn crt_compose(&self, a: &[u64], idx: usize) -> u64 {
-        if self.crt_count == 1 {
-            self.crt_compose_1(a[idx])
-        } else {
-            self.crt_compose_2(a[idx], a[idx + self.poly_len])
+        match self.crt_count {
+            1 => self.crt_compose_1(a[idx]),
+            2 => self.crt_compose_2(a[idx], a[idx + self.poly_len]),
+            _ => self.crt_compose_n(a, idx),
         }
     }
 
+    // Whether every modulus fits the 32-bit NTT and AVX2 kernels.
+    pub fn has_small_moduli(&self) -> bool {
+        self.moduli[..self.crt_count]
+            .iter()
+            .all(|&m| m < 1 << SMALL_MODULUS_BITS)
+    }
+
+    // How many products of small residues can be added to a reduced value in a u64.
+    pub fn max_summed(&self) -> usize {
+        let max_bits = self.moduli[..self.crt_count]
+            .iter()
+            .map(|&m| log2_ceil(m))
+            .max()
+            .unwrap();
+        (1 << u64::min(64u64.saturating_sub(2 * max_bits), 32)) - 1
+    }
+
     pub fn init(
         poly_len: usize,
         moduli: &[u64],
@@ -230,8 +279,8 @@ impl Params {
         db_dim_2: usize,
         instances: usize,
         db_item_size: usize,
-    ) -> Self {
-        assert!(q2_bits >= MIN_Q2_BITS);
+    ) -> Result<Self, ParamsError> {
+        let q2 = q2_for(q2_bits, poly_len)?;
 
         let poly_len_log2 = log2(poly_len as u64) as usize;
         let crt_count = moduli.len();
@@ -255,7 +304,12 @@ impl Params {
             mod0_inv_mod1 = moduli[0] * invert_uint_mod(moduli[0], moduli[1]).unwrap();
             mod1_inv_mod0 = moduli[1] * invert_uint_mod(moduli[1], moduli[0]).unwrap();
         }
-        Self {
+        let mut crt_coeffs = [0; MAX_MODULI];
+        for i in 0..crt_count {
+            let others = modulus / moduli[i];
+            crt_coeffs[i] = others * invert_uint_mod(others % moduli[i], moduli[i]).unwrap();
+        }
+        Ok(Self {
             poly_len,
             poly_len_log2,
             ntt_tables,
@@ -267,6 +321,7 @@ impl Params {
             barrett_cr_1_modulus,
             mod0_inv_mod1,
             mod1_inv_mod0,
+            crt_coeffs,
             moduli: moduli_array,
             modulus,
             modulus_log2,
@@ -274,6 +329,7 @@ impl Params {
             n,
             pt_modulus,
             q2_bits,
+            q2,
             t_conv,
             t_exp_left,
             t_exp_right,
@@ -283,6 +339,712 @@ impl Params {
             db_dim_2,
             instances,
             db_item_size,
+            db_num_items: (1 << db_dim_1) * (1 << db_dim_2),
+        })
+    }
+}
+
+pub const DEFAULT_POLY_LEN: usize = 2048;
+pub const DEFAULT_MODULI_COUNT: usize = 2;
+pub const DEFAULT_MODULUS_BITS: u64 = 28;
+pub const DEFAULT_NOISE_WIDTH: f64 = 6.4;
+
+// The AVX2 kernels work on four coefficients at a time.
+pub const MIN_POLY_LEN: usize = 8;
+// Moduli below 2^30 use the 32-bit NTT and AVX2 kernels: the NTT keeps values below 4q in
+// 32-bit lanes, and the first dimension sums up to 16 products of residues in a u64.
+pub const SMALL_MODULUS_BITS: u64 = 30;
+// Larger moduli use the 64-bit scalar kernels, which keep values below 4q in a u64.
+pub const MAX_MODULUS_BITS: u64 = 62;
+
+// The moduli used when a config does not list any: the largest NTT-friendly primes below
+// 2^DEFAULT_MODULUS_BITS for `poly_len`
+pub fn get_default_moduli(poly_len: usize) -> Vec<u64> {
+    get_ntt_primes(DEFAULT_MODULUS_BITS, poly_len, DEFAULT_MODULI_COUNT)
+}
+
+// The modulus that responses are switched to: the largest NTT-friendly prime below
+// 2^q2_bits for `poly_len`
+pub fn get_q2(q2_bits: u64, poly_len: usize) -> Option<u64> {
+    if q2_bits > MAX_MODULUS_BITS {
+        return None;
+    }
+    get_ntt_primes(q2_bits, poly_len, 1).first().copied()
+}
+
+fn q2_for(q2_bits: u64, poly_len: usize) -> Result<u64, ParamsError> {
+    if q2_bits < MIN_Q2_BITS || q2_bits > MAX_MODULUS_BITS {
+        return Err(ParamsError::invalid(
+            "q2_bits",
+            format!("must be between {} and {}", MIN_Q2_BITS, MAX_MODULUS_BITS),
+        ));
+    }
+    // the client decodes responses with an NTT modulo q2
+    get_q2(q2_bits, poly_len).ok_or_else(|| {
+        ParamsError::invalid(
+            "q2_bits",
+            format!("no prime below 2^{} is 1 mod 2 * poly_len", q2_bits),
+        )
+    })
+}
+
+// Checks that `modulus` supports the negacyclic NTT of length `poly_len`.
+fn check_ntt_modulus(modulus: u64, poly_len: usize) -> Result<(), String> {
+    if modulus >= 1 << MAX_MODULUS_BITS {
+        return Err(format!("{} is not below 2^{}", modulus, MAX_MODULUS_BITS));
+    }
+    if !is_prime(modulus) {
+        return Err(format!("{} is not prime", modulus));
+    }
+    let degree = 2 * poly_len as u64;
+    if modulus % degree != 1 {
+        return Err(format!(
+            "{} is not 1 mod 2 * poly_len = {}",
+            modulus, degree
+        ));
+    }
+    if get_ntt_root(modulus, poly_len).is_none() {
+        return Err(format!(
+            "no primitive {}-th root of unity mod {}",
+            degree, modulus
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum ParamsError {
+    Json(serde_json::Error),
+    InvalidField { field: &'static str, reason: String },
+    Insecure { classical_bits: f64, min_bits: f64 },
+    NotFound { num_items: usize, item_size: usize },
+}
+
+impl ParamsError {
+    fn invalid(field: &'static str, reason: impl Into<String>) -> Self {
+        ParamsError::InvalidField {
+            field,
+            reason: reason.into(),
+        }
+    }
+}
+
+impl fmt::Display for ParamsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamsError::Json(e) => write!(f, "could not parse parameters: {}", e),
+            ParamsError::InvalidField { field, reason } => {
+                write!(f, "invalid parameter `{}`: {}", field, reason)
+            }
+            ParamsError::Insecure {
+                classical_bits,
+                min_bits,
+            } => write!(
+                f,
+                "parameters give an estimated {:.1} bits of security, below {}",
+                classical_bits, min_bits
+            ),
+            ParamsError::NotFound {
+                num_items,
+                item_size,
+            } => write!(
+                f,
+                "no parameters for {} items of {} bytes reach the target failure probability",
+                num_items, item_size
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParamsError {}
+
+impl From<serde_json::Error> for ParamsError {
+    fn from(e: serde_json::Error) -> Self {
+        ParamsError::Json(e)
+    }
+}
+
+// Mirrors the JSON parameter files; field names are the ones used there.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ParamsConfig {
+    pub n: usize,
+    pub nu_1: usize,
+    pub nu_2: usize,
+    pub p: u64,
+    pub q2_bits: u64,
+    pub t_gsw: usize,
+    pub t_conv: usize,
+    pub t_exp_left: usize,
+    pub t_exp_right: usize,
+    #[serde(default = "default_instances")]
+    pub instances: usize,
+    #[serde(default)]
+    pub db_item_size: usize,
+    // Left out when every slot holds an item.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub num_items: Option<usize>,
+    // Presence of this field (with any value) disables query expansion.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub direct_upload: Option<u64>,
+    #[serde(
+        default = "default_poly_len",
+        skip_serializing_if = "is_default_poly_len"
+    )]
+    pub poly_len: usize,
+    // Left empty for the default moduli of `poly_len`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub moduli: Vec<u64>,
+    #[serde(
+        default = "default_noise_width",
+        skip_serializing_if = "is_default_noise_width"
+    )]
+    pub noise_width: f64,
+    // Estimated noise, informational only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub s_e: Option<f64>,
+}
+
+fn default_instances() -> usize {
+    1
+}
+
+fn default_poly_len() -> usize {
+    DEFAULT_POLY_LEN
+}
+
+fn is_default_poly_len(poly_len: &usize) -> bool {
+    *poly_len == DEFAULT_POLY_LEN
+}
+
+fn default_noise_width() -> f64 {
+    DEFAULT_NOISE_WIDTH
+}
+
+fn is_default_noise_width(noise_width: &f64) -> bool {
+    *noise_width == DEFAULT_NOISE_WIDTH
+}
+
+impl From<&Params> for ParamsConfig {
+    fn from(params: &Params) -> Self {
+        let moduli = &params.moduli[..params.crt_count];
+        ParamsConfig {
+            n: params.n,
+            nu_1: params.db_dim_1,
+            nu_2: params.db_dim_2,
+            p: params.pt_modulus,
+            q2_bits: params.q2_bits,
+            t_gsw: params.t_gsw,
+            t_conv: params.t_conv,
+            t_exp_left: params.t_exp_left,
+            t_exp_right: params.t_exp_right,
+            instances: params.instances,
+            db_item_size: params.db_item_size,
+            num_items: if params.num_items() == params.num_slots() {
+                None
+            } else {
+                Some(params.num_items())
+            },
+            direct_upload: if params.expand_queries { None } else { Some(1) },
+            poly_len: params.poly_len,
+            moduli: if moduli == get_default_moduli(params.poly_len) {
+                Vec::new()
+            } else {
+                moduli.to_vec()
+            },
+            noise_width: params.noise_width,
+            s_e: None,
+        }
+    }
+}
+
+impl TryFrom<ParamsConfig> for Params {
+    type Error = ParamsError;
+
+    fn try_from(cfg: ParamsConfig) -> Result<Self, Self::Error> {
+        let poly_len = cfg.poly_len;
+        let noise_width = cfg.noise_width;
+        let expand_queries = cfg.direct_upload.is_none();
+
+        if poly_len < MIN_POLY_LEN || !poly_len.is_power_of_two() {
+            return Err(ParamsError::invalid(
+                "poly_len",
+                format!("must be a power of two, at least {}", MIN_POLY_LEN),
+            ));
+        }
+        let moduli = if cfg.moduli.is_empty() {
+            get_default_moduli(poly_len)
+        } else {
+            cfg.moduli
+        };
+        if moduli.len() > MAX_MODULI {
+            return Err(ParamsError::invalid(
+                "moduli",
+                format!("at most {} moduli are supported", MAX_MODULI),
+            ));
+        }
+        if poly_len * moduli.len() > SCRATCH_SPACE {
+            return Err(ParamsError::invalid(
+                "poly_len",
+                format!(
+                    "poly_len * {} moduli must be at most {}",
+                    moduli.len(),
+                    SCRATCH_SPACE
+                ),
+            ));
+        }
+        for (i, &m) in moduli.iter().enumerate() {
+            check_ntt_modulus(m, poly_len).map_err(|e| ParamsError::invalid("moduli", e))?;
+            if moduli[..i].contains(&m) {
+                return Err(ParamsError::invalid(
+                    "moduli",
+                    format!("{} appears more than once", m),
+                ));
+            }
+        }
+        // reorient_reg_ciphertexts and the database packing put all residues in one u64
+        let residue_bits = u64::min(SMALL_MODULUS_BITS, 64 / moduli.len() as u64);
+        if moduli.len() > 1 && moduli.iter().any(|&m| m >= 1 << residue_bits) {
+            return Err(ParamsError::invalid(
+                "moduli",
+                format!(
+                    "with {} moduli, each must be below 2^{}",
+                    moduli.len(),
+                    residue_bits
+                ),
+            ));
+        }
+        let modulus = moduli
+            .iter()
+            .try_fold(1u64, |acc, &m| acc.checked_mul(m))
+            .filter(|&q| q < 1 << MAX_MODULUS_BITS)
+            .ok_or_else(|| {
+                ParamsError::invalid(
+                    "moduli",
+                    format!("the product must be below 2^{}", MAX_MODULUS_BITS),
+                )
+            })?;
+
+        if !noise_width.is_finite() || noise_width <= 0.0 {
+            return Err(ParamsError::invalid("noise_width", "must be positive"));
+        }
+
+        if cfg.n == 0 {
+            return Err(ParamsError::invalid("n", "must be at least 1"));
+        }
+        if cfg.instances == 0 {
+            return Err(ParamsError::invalid("instances", "must be at least 1"));
+        }
+        if cfg.p < 2 || !cfg.p.is_power_of_two() {
+            return Err(ParamsError::invalid(
+                "p",
+                "must be a power of two, at least 2",
+            ));
+        }
+        if cfg.p.checked_mul(4).is_none_or(|x| x >= modulus) {
+            return Err(ParamsError::invalid(
+                "p",
+                format!("4 * p must be below the modulus {}", modulus),
+            ));
+        }
+
+        let q2 = q2_for(cfg.q2_bits, poly_len)?;
+        if q2 >= modulus {
+            return Err(ParamsError::invalid(
+                "q2_bits",
+                "q2 must be below the modulus",
+            ));
+        }
+
+        for (field, t) in [
+            ("t_gsw", cfg.t_gsw),
+            ("t_conv", cfg.t_conv),
+            ("t_exp_left", cfg.t_exp_left),
+            ("t_exp_right", cfg.t_exp_right),
+        ] {
+            if t == 0 {
+                return Err(ParamsError::invalid(field, "must be at least 1"));
+            }
+        }
+        if expand_queries {
+            // expansion hands gadget digits to the NTT without reducing them, which only
+            // accepts inputs below 4q
+            let min_modulus = *moduli.iter().min().unwrap();
+            for (field, t) in [
+                ("t_exp_left", cfg.t_exp_left),
+                ("t_exp_right", cfg.t_exp_right),
+            ] {
+                let bits_per = bits_per_digit(log2_ceil(modulus), t);
+                if 1u64 << bits_per > 4 * min_modulus {
+                    return Err(ParamsError::invalid(
+                        field,
+                        format!(
+                            "{}-bit digits do not fit below 4 * {}",
+                            bits_per, min_modulus
+                        ),
+                    ));
+                }
+            }
+        }
+
+        let poly_len_log2 = log2(poly_len as u64) as usize;
+        // the first dimension is processed two ciphertext rows at a time, four lanes wide
+        if cfg.nu_1 == 0 {
+            return Err(ParamsError::invalid("nu_1", "must be at least 1"));
+        }
+        if cfg.nu_1 + cfg.nu_2 >= usize::BITS as usize {
+            return Err(ParamsError::invalid("nu_2", "nu_1 + nu_2 is too large"));
+        }
+        if expand_queries {
+            // all query bits are packed into the coefficients of one ciphertext
+            let num_bits_to_gen = cfg
+                .t_gsw
+                .checked_mul(cfg.nu_2)
+                .and_then(|x| x.checked_add(1 << cfg.nu_1));
+            if num_bits_to_gen.is_none_or(|x| log2_ceil_usize(x) > poly_len_log2) {
+                return Err(ParamsError::invalid(
+                    "nu_1",
+                    format!(
+                        "2^nu_1 + t_gsw * nu_2 does not fit in {} coefficients",
+                        poly_len
+                    ),
+                ));
+            }
+        }
+
+        let logp = log2(cfg.p) as usize;
+        let chunks = cfg
+            .instances
+            .checked_mul(cfg.n)
+            .and_then(|x| x.checked_mul(cfg.n))
+            .ok_or_else(|| ParamsError::invalid("n", "instances * n * n is too large"))?;
+        let mut db_item_size = cfg.db_item_size;
+        if db_item_size == 0 {
+            db_item_size = chunks
+                .checked_mul(poly_len * logp)
+                .ok_or_else(|| ParamsError::invalid("n", "instances * n * n is too large"))?
+                / 8;
+        }
+        let bytes_per_chunk = f64::ceil(db_item_size as f64 / chunks as f64) as usize;
+        let modp_words_per_chunk = f64::ceil((bytes_per_chunk as f64 * 8.0) / logp as f64) as usize;
+        if modp_words_per_chunk > poly_len {
+            return Err(ParamsError::invalid(
+                "db_item_size",
+                format!(
+                    "needs {} words of {} bits per polynomial, but poly_len is {}",
+                    modp_words_per_chunk, logp, poly_len
+                ),
+            ));
+        }
+
+        let num_slots = (1 << cfg.nu_1) * (1 << cfg.nu_2);
+        let num_items = cfg.num_items.unwrap_or(num_slots);
+        if num_items == 0 || num_items > num_slots {
+            return Err(ParamsError::invalid(
+                "num_items",
+                format!("must be between 1 and 2^(nu_1 + nu_2) = {}", num_slots),
+            ));
+        }
+
+        let mut params = Params::init(
+            poly_len,
+            &moduli,
+            noise_width,
+            cfg.n,
+            cfg.p,
+            cfg.q2_bits,
+            cfg.t_conv,
+            cfg.t_exp_left,
+            cfg.t_exp_right,
+            cfg.t_gsw,
+            expand_queries,
+            cfg.nu_1,
+            cfg.nu_2,
+            cfg.instances,
+            db_item_size,
+        )?;
+        params.db_num_items = num_items;
+        Ok(params)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::get_seeded_rng;
+    use rand::Rng;
+
+    fn get_config() -> ParamsConfig {
+        serde_json::from_str(
+            r#"
+            {"n": 2,
+            "nu_1": 9,
+            "nu_2": 6,
+            "p": 256,
+            "q2_bits": 20,
+            "t_gsw": 8,
+            "t_conv": 4,
+            "t_exp_left": 8,
+            "t_exp_right": 56,
+            "instances": 1,
+            "db_item_size": 2048 }
+        "#,
+        )
+        .unwrap()
+    }
+
+    fn invalid_field(cfg: ParamsConfig) -> &'static str {
+        match Params::try_from(cfg) {
+            Err(ParamsError::InvalidField { field, .. }) => field,
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("parameters should have been rejected"),
         }
     }
+
+    #[test]
+    fn params_config_accepts_valid() {
+        let params = Params::try_from(get_config()).unwrap();
+        assert_eq!(params.crt_count, 2);
+        assert_eq!(params.db_item_size, 2048);
+        assert!(params.expand_queries);
+    }
+
+    #[test]
+    fn params_config_rejects_invalid_fields() {
+        let mut cfg = get_config();
+        cfg.q2_bits = MIN_Q2_BITS - 1;
+        assert_eq!(invalid_field(cfg), "q2_bits");
+
+        let mut cfg = get_config();
+        cfg.p = 300;
+        assert_eq!(invalid_field(cfg), "p");
+
+        let mut cfg = get_config();
+        cfg.t_exp_left = 0;
+        assert_eq!(invalid_field(cfg), "t_exp_left");
+
+        let mut cfg = get_config();
+        cfg.nu_1 = 11;
+        assert_eq!(invalid_field(cfg), "nu_1");
+
+        let mut cfg = get_config();
+        cfg.db_item_size = 100000;
+        assert_eq!(invalid_field(cfg), "db_item_size");
+
+        // values whose checks would overflow
+        for p in [1 << 62, 1 << 63] {
+            let mut cfg = get_config();
+            cfg.p = p;
+            assert_eq!(invalid_field(cfg), "p");
+        }
+        let mut cfg = get_config();
+        cfg.t_gsw = usize::MAX;
+        assert_eq!(invalid_field(cfg), "nu_1");
+        let mut cfg = get_config();
+        cfg.nu_1 = 62;
+        cfg.nu_2 = 1;
+        cfg.t_gsw = usize::MAX / 2;
+        assert_eq!(invalid_field(cfg), "nu_1");
+        let mut cfg = get_config();
+        cfg.n = 1 << 32;
+        assert_eq!(invalid_field(cfg), "n");
+        let mut cfg = get_config();
+        cfg.db_item_size = usize::MAX;
+        assert_eq!(invalid_field(cfg), "db_item_size");
+    }
+
+    #[test]
+    fn params_config_accepts_item_count() {
+        let params = Params::try_from(get_config()).unwrap();
+        assert_eq!(params.num_items(), 1 << 15);
+        assert_eq!(params.num_slots(), 1 << 15);
+        assert_eq!(ParamsConfig::from(&params).num_items, None);
+
+        let mut cfg = get_config();
+        cfg.num_items = Some(20000);
+        let params = Params::try_from(cfg.clone()).unwrap();
+        assert_eq!(params.num_items(), 20000);
+        assert_eq!(params.num_slots(), 1 << 15);
+        assert_eq!(ParamsConfig::from(&params), cfg);
+
+        for num_items in [0, (1 << 15) + 1] {
+            let mut cfg = get_config();
+            cfg.num_items = Some(num_items);
+            assert_eq!(invalid_field(cfg), "num_items");
+        }
+    }
+
+    #[test]
+    fn params_config_accepts_ring_dimension_and_moduli() {
+        let mut cfg = get_config();
+        cfg.poly_len = 4096;
+        cfg.moduli = vec![268361729, 268271617];
+        cfg.noise_width = 3.2;
+        let params = Params::try_from(cfg.clone()).unwrap();
+        assert_eq!(params.poly_len, 4096);
+        assert_eq!(params.poly_len_log2, 12);
+        assert_eq!(params.modulus, 268361729 * 268271617);
+        assert_eq!(params.noise_width, 3.2);
+        assert_eq!(ParamsConfig::from(&params), cfg);
+
+        let json = r#"{"n": 2, "nu_1": 9, "nu_2": 6, "p": 256, "q2_bits": 20, "t_gsw": 8,
+            "t_conv": 4, "t_exp_left": 8, "t_exp_right": 56, "poly_len": 1024}"#;
+        let cfg: ParamsConfig = serde_json::from_str(json).unwrap();
+        assert!(cfg.moduli.is_empty());
+        let params = Params::try_from(cfg.clone()).unwrap();
+        assert_eq!(params.poly_len, 1024);
+        assert_eq!(params.moduli[..params.crt_count], get_default_moduli(1024));
+        assert!(ParamsConfig::from(&params).moduli.is_empty());
+    }
+
+    #[test]
+    fn default_moduli_and_q2_are_ntt_friendly() {
+        assert_eq!(get_default_moduli(DEFAULT_POLY_LEN), DEFAULT_MODULI_2048);
+        assert_eq!(get_default_moduli(1 << 16), vec![268042241, 265420801]);
+
+        assert_eq!(get_q2(20, 2048), Some(1032193));
+        assert_eq!(get_q2(21, 2048), Some(2056193));
+        assert_eq!(get_q2(15, 2048), Some(12289));
+        assert_eq!(get_q2(14, 8192), None);
+        assert_eq!(get_q2(MAX_MODULUS_BITS + 1, 2048), None);
+        for q2_bits in MIN_Q2_BITS..=MAX_MODULUS_BITS {
+            let q2 = get_q2(q2_bits, 2048).unwrap();
+            assert!(q2 < 1 << q2_bits);
+            assert!(check_ntt_modulus(q2, 2048).is_ok());
+        }
+
+        let params = Params::try_from(get_config()).unwrap();
+        assert_eq!(params.q2, 1032193);
+    }
+
+    #[test]
+    fn params_config_rejects_unsupported_rings() {
+        let mut cfg = get_config();
+        cfg.poly_len = 1000;
+        assert_eq!(invalid_field(cfg), "poly_len");
+
+        let mut cfg = get_config();
+        cfg.poly_len = 8192;
+        assert_eq!(invalid_field(cfg), "poly_len");
+
+        // not prime
+        let mut cfg = get_config();
+        cfg.moduli = vec![268369921, 268369921 * 3];
+        assert_eq!(invalid_field(cfg), "moduli");
+
+        // 12289 is 1 mod 4096 but not mod 8192
+        let mut cfg = get_config();
+        cfg.moduli = vec![268369921, 12289];
+        cfg.poly_len = 4096;
+        assert_eq!(invalid_field(cfg), "moduli");
+
+        // too large to pack two residues
+        let mut cfg = get_config();
+        cfg.moduli = vec![268369921, 2013265921];
+        assert_eq!(invalid_field(cfg), "moduli");
+
+        let mut cfg = get_config();
+        cfg.moduli = vec![268369921, 268369921];
+        assert_eq!(invalid_field(cfg), "moduli");
+
+        let mut cfg = get_config();
+        cfg.moduli = vec![268369921; MAX_MODULI + 1];
+        assert_eq!(invalid_field(cfg), "moduli");
+
+        let mut cfg = get_config();
+        cfg.poly_len = 4096;
+        cfg.q2_bits = 14;
+        assert_eq!(invalid_field(cfg), "q2_bits");
+
+        let mut cfg = get_config();
+        cfg.noise_width = 0.0;
+        assert_eq!(invalid_field(cfg), "noise_width");
+    }
+
+    const SINGLE_MODULUS: [u64; 1] = [72057594037616641];
+    const DEFAULT_MODULI_2048: [u64; 2] = [268369921, 268361729];
+    const THREE_MODULI: [u64; 3] = [1032193, 974849, 925697];
+
+    #[test]
+    fn params_config_accepts_one_or_three_moduli() {
+        for moduli in [&SINGLE_MODULUS[..], &THREE_MODULI[..]] {
+            let mut cfg = get_config();
+            cfg.moduli = moduli.to_vec();
+            let params = Params::try_from(cfg).unwrap();
+            assert_eq!(params.crt_count, moduli.len());
+            assert_eq!(params.modulus, moduli.iter().product::<u64>());
+            assert_eq!(params.has_small_moduli(), moduli.len() > 1);
+        }
+
+        // three residues must fit in 21-bit slots, and their product below 2^62
+        let mut cfg = get_config();
+        cfg.moduli = vec![268369921, 1032193, 974849];
+        assert_eq!(invalid_field(cfg), "moduli");
+
+        let mut cfg = get_config();
+        cfg.moduli = vec![1785857, 1769473, 1720321];
+        assert_eq!(invalid_field(cfg), "moduli");
+
+        let mut cfg = get_config();
+        cfg.moduli = vec![SINGLE_MODULUS[0], 268369921];
+        assert_eq!(invalid_field(cfg), "moduli");
+    }
+
+    #[test]
+    fn crt_compose_is_correct() {
+        let mut rng = get_seeded_rng();
+        for moduli in [
+            &SINGLE_MODULUS[..],
+            &DEFAULT_MODULI_2048[..],
+            &THREE_MODULI[..],
+        ] {
+            let mut cfg = get_config();
+            cfg.moduli = moduli.to_vec();
+            let params = Params::try_from(cfg).unwrap();
+
+            let mut a = vec![0u64; params.crt_count * params.poly_len];
+            let vals: Vec<u64> = (0..16).map(|_| rng.gen::<u64>() % params.modulus).collect();
+            for (z, &val) in vals.iter().enumerate() {
+                for n in 0..params.crt_count {
+                    a[n * params.poly_len + z] = val % moduli[n];
+                }
+            }
+            for (z, &val) in vals.iter().enumerate() {
+                assert_eq!(params.crt_compose(&a, z), val);
+                assert_eq!(params.crt_compose_n(&a, z), val);
+            }
+        }
+    }
+
+    #[test]
+    fn fingerprint_is_stable() {
+        let params = Params::try_from(get_config()).unwrap();
+        assert_eq!(params.fingerprint(), 0xb0adaa77acc28782);
+
+        let mut other_q2 = params.clone();
+        other_q2.q2 = 786433;
+        assert_ne!(params.fingerprint(), other_q2.fingerprint());
+
+        let mut cfg = get_config();
+        cfg.num_items = Some(params.num_slots());
+        assert_eq!(
+            Params::try_from(cfg).unwrap().fingerprint(),
+            params.fingerprint()
+        );
+        let mut cfg = get_config();
+        cfg.num_items = Some(params.num_slots() - 1);
+        let fewer_items = Params::try_from(cfg).unwrap();
+        assert_ne!(params.fingerprint(), fewer_items.fingerprint());
+
+        let mut cfg = get_config();
+        cfg.t_exp_right = 57;
+        let other = Params::try_from(cfg).unwrap();
+        assert_ne!(params.fingerprint(), other.fingerprint());
+    }
+
+    #[test]
+    fn params_config_names_unknown_fields() {
+        let err = serde_json::from_str::<ParamsConfig>(r#"{"n": 2, "nu_3": 1}"#).unwrap_err();
+        assert!(err.to_string().contains("nu_3"));
+    }
 }
@@ -2,25 +2,25 @@
 use std::arch::x86_64::*;
 
 use rand::distributions::Standard;
-use rand::Rng;
-use rand_chacha::ChaCha20Rng;
+use rand::{Rng, RngCore};
 use std::cell::RefCell;
 use std::ops::{Add, Mul, Neg};
+use std::sync::Arc;
 
 use crate::{aligned_memory::*, arith::*, discrete_gaussian::*, ntt::*, params::*, util::*};
 
-const SCRATCH_SPACE: usize = 8192;
+pub const SCRATCH_SPACE: usize = 8192;
 thread_local!(static SCRATCH: RefCell<AlignedMemory64> = RefCell::new(AlignedMemory64::new(SCRATCH_SPACE)));
 
-pub trait PolyMatrix<'a> {
+pub trait PolyMatrix {
     fn is_ntt(&self) -> bool;
     fn get_rows(&self) -> usize;
     fn get_cols(&self) -> usize;
-    fn get_params(&self) -> &Params;
+    fn get_params(&self) -> &Arc<Params>;
     fn num_words(&self) -> usize;
-    fn zero(params: &'a Params, rows: usize, cols: usize) -> Self;
-    fn random(params: &'a Params, rows: usize, cols: usize) -> Self;
-    fn random_rng<T: Rng>(params: &'a Params, rows: usize, cols: usize, rng: &mut T) -> Self;
+    fn zero(params: &Arc<Params>, rows: usize, cols: usize) -> Self;
+    fn random(params: &Arc<Params>, rows: usize, cols: usize) -> Self;
+    fn random_rng<T: Rng>(params: &Arc<Params>, rows: usize, cols: usize, rng: &mut T) -> Self;
     fn as_slice(&self) -> &[u64];
     fn as_mut_slice(&mut self) -> &mut [u64];
     fn zero_out(&mut self) {
@@ -56,21 +56,21 @@ pub trait PolyMatrix<'a> {
     fn pad_top(&self, pad_rows: usize) -> Self;
 }
 
-pub struct PolyMatrixRaw<'a> {
-    pub params: &'a Params,
+pub struct PolyMatrixRaw {
+    pub params: Arc<Params>,
     pub rows: usize,
     pub cols: usize,
     pub data: AlignedMemory64,
 }
 
-pub struct PolyMatrixNTT<'a> {
-    pub params: &'a Params,
+pub struct PolyMatrixNTT {
+    pub params: Arc<Params>,
     pub rows: usize,
     pub cols: usize,
     pub data: AlignedMemory64,
 }
 
-impl<'a> PolyMatrix<'a> for PolyMatrixRaw<'a> {
+impl PolyMatrix for PolyMatrixRaw {
     fn is_ntt(&self) -> bool {
         false
     }
@@ -80,7 +80,7 @@ impl<'a> PolyMatrix<'a> for PolyMatrixRaw<'a> {
     fn get_cols(&self) -> usize {
         self.cols
     }
-    fn get_params(&self) -> &Params {
+    fn get_params(&self) -> &Arc<Params> {
         &self.params
     }
     fn as_slice(&self) -> &[u64] {
@@ -92,17 +92,17 @@ impl<'a> PolyMatrix<'a> for PolyMatrixRaw<'a> {
     fn num_words(&self) -> usize {
         self.params.poly_len
     }
-    fn zero(params: &'a Params, rows: usize, cols: usize) -> PolyMatrixRaw<'a> {
+    fn zero(params: &Arc<Params>, rows: usize, cols: usize) -> PolyMatrixRaw {
         let num_coeffs = rows * cols * params.poly_len;
         let data = AlignedMemory64::new(num_coeffs);
         PolyMatrixRaw {
-            params,
+            params: params.clone(),
             rows,
             cols,
             data,
         }
     }
-    fn random_rng<T: Rng>(params: &'a Params, rows: usize, cols: usize, rng: &mut T) -> Self {
+    fn random_rng<T: Rng>(params: &Arc<Params>, rows: usize, cols: usize, rng: &mut T) -> Self {
         let mut iter = rng.sample_iter(&Standard);
         let mut out = PolyMatrixRaw::zero(params, rows, cols);
         for r in 0..rows {
@@ -115,17 +115,22 @@ impl<'a> PolyMatrix<'a> for PolyMatrixRaw<'a> {
         }
         out
     }
-    fn random(params: &'a Params, rows: usize, cols: usize) -> Self {
+    fn random(params: &Arc<Params>, rows: usize, cols: usize) -> Self {
         let mut rng = rand::thread_rng();
         Self::random_rng(params, rows, cols, &mut rng)
     }
     fn pad_top(&self, pad_rows: usize) -> Self {
-        let mut padded = Self::zero(self.params, self.rows + pad_rows, self.cols);
+        let mut padded = Self::zero_secret_if(
+            &self.params,
+            self.rows + pad_rows,
+            self.cols,
+            self.data.is_secret(),
+        );
         padded.copy_into(&self, pad_rows, 0);
         padded
     }
     fn submatrix(&self, target_row: usize, target_col: usize, rows: usize, cols: usize) -> Self {
-        let mut m = Self::zero(self.params, rows, cols);
+        let mut m = Self::zero_secret_if(&self.params, rows, cols, self.data.is_secret());
         assert!(target_row < self.rows);
         assert!(target_col < self.cols);
         assert!(target_row + rows <= self.rows);
@@ -141,23 +146,38 @@ impl<'a> PolyMatrix<'a> for PolyMatrixRaw<'a> {
     }
 }
 
-impl<'a> Clone for PolyMatrixRaw<'a> {
+impl Clone for PolyMatrixRaw {
     fn clone(&self) -> Self {
-        let mut data_clone = AlignedMemory64::new(self.data.len());
-        data_clone
-            .as_mut_slice()
-            .copy_from_slice(self.data.as_slice());
         PolyMatrixRaw {
-            params: self.params,
+            params: self.params.clone(),
             rows: self.rows,
             cols: self.cols,
-            data: data_clone,
+            data: self.data.clone(),
         }
     }
 }
 
-impl<'a> PolyMatrixRaw<'a> {
-    pub fn identity(params: &'a Params, rows: usize, cols: usize) -> PolyMatrixRaw<'a> {
+impl PolyMatrixRaw {
+    // Like `zero`, for a matrix that will hold secrets: its memory is zeroized when dropped
+    pub fn zero_secret(params: &Arc<Params>, rows: usize, cols: usize) -> Self {
+        let num_coeffs = rows * cols * params.poly_len;
+        PolyMatrixRaw {
+            params: params.clone(),
+            rows,
+            cols,
+            data: AlignedMemory64::new_secret(num_coeffs),
+        }
+    }
+
+    pub fn zero_secret_if(params: &Arc<Params>, rows: usize, cols: usize, secret: bool) -> Self {
+        if secret {
+            Self::zero_secret(params, rows, cols)
+        } else {
+            Self::zero(params, rows, cols)
+        }
+    }
+
+    pub fn identity(params: &Arc<Params>, rows: usize, cols: usize) -> PolyMatrixRaw {
         let num_coeffs = rows * cols * params.poly_len;
         let mut data = AlignedMemory::new(num_coeffs);
         for r in 0..rows {
@@ -166,26 +186,26 @@ impl<'a> PolyMatrixRaw<'a> {
             data[idx] = 1;
         }
         PolyMatrixRaw {
-            params,
+            params: params.clone(),
             rows,
             cols,
             data,
         }
     }
 
-    pub fn noise(
-        params: &'a Params,
+    pub fn noise<R: RngCore>(
+        params: &Arc<Params>,
         rows: usize,
         cols: usize,
         dg: &DiscreteGaussian,
-        rng: &mut ChaCha20Rng,
+        rng: &mut R,
     ) -> Self {
-        let mut out = PolyMatrixRaw::zero(params, rows, cols);
+        let mut out = PolyMatrixRaw::zero_secret(params, rows, cols);
         dg.sample_matrix(&mut out, rng);
         out
     }
 
-    pub fn ntt(&self) -> PolyMatrixNTT<'a> {
+    pub fn ntt(&self) -> PolyMatrixNTT {
         to_ntt_alloc(&self)
     }
 
@@ -234,14 +254,14 @@ impl<'a> PolyMatrixRaw<'a> {
         data
     }
 
-    pub fn single_value(params: &'a Params, value: u64) -> PolyMatrixRaw<'a> {
+    pub fn single_value(params: &Arc<Params>, value: u64) -> PolyMatrixRaw {
         let mut out = Self::zero(params, 1, 1);
         out.data[0] = value;
         out
     }
 }
 
-impl<'a> PolyMatrix<'a> for PolyMatrixNTT<'a> {
+impl PolyMatrix for PolyMatrixNTT {
     fn is_ntt(&self) -> bool {
         true
     }
@@ -251,7 +271,7 @@ impl<'a> PolyMatrix<'a> for PolyMatrixNTT<'a> {
     fn get_cols(&self) -> usize {
         self.cols
     }
-    fn get_params(&self) -> &Params {
+    fn get_params(&self) -> &Arc<Params> {
         &self.params
     }
     fn as_slice(&self) -> &[u64] {
@@ -263,17 +283,17 @@ impl<'a> PolyMatrix<'a> for PolyMatrixNTT<'a> {
     fn num_words(&self) -> usize {
         self.params.poly_len * self.params.crt_count
     }
-    fn zero(params: &'a Params, rows: usize, cols: usize) -> PolyMatrixNTT<'a> {
+    fn zero(params: &Arc<Params>, rows: usize, cols: usize) -> PolyMatrixNTT {
         let num_coeffs = rows * cols * params.poly_len * params.crt_count;
         let data = AlignedMemory::new(num_coeffs);
         PolyMatrixNTT {
-            params,
+            params: params.clone(),
             rows,
             cols,
             data,
         }
     }
-    fn random_rng<T: Rng>(params: &'a Params, rows: usize, cols: usize, rng: &mut T) -> Self {
+    fn random_rng<T: Rng>(params: &Arc<Params>, rows: usize, cols: usize, rng: &mut T) -> Self {
         let mut iter = rng.sample_iter(&Standard);
         let mut out = PolyMatrixNTT::zero(params, rows, cols);
         for r in 0..rows {
@@ -289,18 +309,23 @@ impl<'a> PolyMatrix<'a> for PolyMatrixNTT<'a> {
         }
         out
     }
-    fn random(params: &'a Params, rows: usize, cols: usize) -> Self {
+    fn random(params: &Arc<Params>, rows: usize, cols: usize) -> Self {
         let mut rng = rand::thread_rng();
         Self::random_rng(params, rows, cols, &mut rng)
     }
     fn pad_top(&self, pad_rows: usize) -> Self {
-        let mut padded = Self::zero(self.params, self.rows + pad_rows, self.cols);
+        let mut padded = Self::zero_secret_if(
+            &self.params,
+            self.rows + pad_rows,
+            self.cols,
+            self.data.is_secret(),
+        );
         padded.copy_into(&self, pad_rows, 0);
         padded
     }
 
     fn submatrix(&self, target_row: usize, target_col: usize, rows: usize, cols: usize) -> Self {
-        let mut m = Self::zero(self.params, rows, cols);
+        let mut m = Self::zero_secret_if(&self.params, rows, cols, self.data.is_secret());
         assert!(target_row < self.rows);
         assert!(target_col < self.cols);
         assert!(target_row + rows <= self.rows);
@@ -316,23 +341,38 @@ impl<'a> PolyMatrix<'a> for PolyMatrixNTT<'a> {
     }
 }
 
-impl<'a> Clone for PolyMatrixNTT<'a> {
+impl Clone for PolyMatrixNTT {
     fn clone(&self) -> Self {
-        let mut data_clone = AlignedMemory64::new(self.data.len());
-        data_clone
-            .as_mut_slice()
-            .copy_from_slice(self.data.as_slice());
         PolyMatrixNTT {
-            params: self.params,
+            params: self.params.clone(),
             rows: self.rows,
             cols: self.cols,
-            data: data_clone,
+            data: self.data.clone(),
         }
     }
 }
 
-impl<'a> PolyMatrixNTT<'a> {
-    pub fn raw(&self) -> PolyMatrixRaw<'a> {
+impl PolyMatrixNTT {
+    // Like `zero`, for a matrix that will hold secrets: its memory is zeroized when dropped
+    pub fn zero_secret(params: &Arc<Params>, rows: usize, cols: usize) -> Self {
+        let num_coeffs = rows * cols * params.poly_len * params.crt_count;
+        PolyMatrixNTT {
+            params: params.clone(),
+            rows,
+            cols,
+            data: AlignedMemory64::new_secret(num_coeffs),
+        }
+    }
+
+    pub fn zero_secret_if(params: &Arc<Params>, rows: usize, cols: usize, secret: bool) -> Self {
+        if secret {
+            Self::zero_secret(params, rows, cols)
+        } else {
+            Self::zero(params, rows, cols)
+        }
+    }
+
+    pub fn raw(&self) -> PolyMatrixRaw {
         from_ntt_alloc(&self)
     }
 }
@@ -423,36 +463,46 @@ pub fn modular_reduce(params: &Params, res: &mut [u64]) {
     }
 }
 
-#[cfg(not(target_feature = "avx2"))]
-pub fn multiply(res: &mut PolyMatrixNTT, a: &PolyMatrixNTT, b: &PolyMatrixNTT) {
+fn multiply_scalar(res: &mut PolyMatrixNTT, a: &PolyMatrixNTT, b: &PolyMatrixNTT) {
     assert!(res.rows == a.rows);
     assert!(res.cols == b.cols);
     assert!(a.cols == b.rows);
 
-    let params = res.params;
+    let params = res.params.clone();
     for i in 0..a.rows {
         for j in 0..b.cols {
             for z in 0..params.poly_len * params.crt_count {
                 res.get_poly_mut(i, j)[z] = 0;
             }
             for k in 0..a.cols {
-                let params = res.params;
+                let params = res.params.clone();
                 let res_poly = res.get_poly_mut(i, j);
                 let pol1 = a.get_poly(i, k);
                 let pol2 = b.get_poly(k, j);
-                multiply_add_poly(params, res_poly, pol1, pol2);
+                multiply_add_poly(&params, res_poly, pol1, pol2);
             }
         }
     }
 }
 
+#[cfg(not(target_feature = "avx2"))]
+pub fn multiply(res: &mut PolyMatrixNTT, a: &PolyMatrixNTT, b: &PolyMatrixNTT) {
+    multiply_scalar(res, a, b);
+}
+
 #[cfg(target_feature = "avx2")]
 pub fn multiply(res: &mut PolyMatrixNTT, a: &PolyMatrixNTT, b: &PolyMatrixNTT) {
     assert_eq!(res.rows, a.rows);
     assert_eq!(res.cols, b.cols);
     assert_eq!(a.cols, b.rows);
 
-    let params = res.params;
+    let params = res.params.clone();
+    if !params.has_small_moduli() {
+        multiply_scalar(res, a, b);
+        return;
+    }
+
+    let max_summed = params.max_summed();
     for i in 0..a.rows {
         for j in 0..b.cols {
             for z in 0..params.poly_len * params.crt_count {
@@ -462,9 +512,12 @@ pub fn multiply(res: &mut PolyMatrixNTT, a: &PolyMatrixNTT, b: &PolyMatrixNTT) {
             for k in 0..a.cols {
                 let pol1 = a.get_poly(i, k);
                 let pol2 = b.get_poly(k, j);
-                multiply_add_poly_avx(params, res_poly, pol1, pol2);
+                multiply_add_poly_avx(&params, res_poly, pol1, pol2);
+                if (k + 1) % max_summed == 0 {
+                    modular_reduce(&params, res_poly);
+                }
             }
-            modular_reduce(params, res_poly);
+            modular_reduce(&params, res_poly);
         }
     }
 }
@@ -475,13 +528,13 @@ pub fn add(res: &mut PolyMatrixNTT, a: &PolyMatrixNTT, b: &PolyMatrixNTT) {
     assert!(a.rows == b.rows);
     assert!(a.cols == b.cols);
 
-    let params = res.params;
+    let params = res.params.clone();
     for i in 0..a.rows {
         for j in 0..a.cols {
             let res_poly = res.get_poly_mut(i, j);
             let pol1 = a.get_poly(i, j);
             let pol2 = b.get_poly(i, j);
-            add_poly(params, res_poly, pol1, pol2);
+            add_poly(&params, res_poly, pol1, pol2);
         }
     }
 }
@@ -490,23 +543,23 @@ pub fn add_into(res: &mut PolyMatrixNTT, a: &PolyMatrixNTT) {
     assert!(res.rows == a.rows);
     assert!(res.cols == a.cols);
 
-    let params = res.params;
+    let params = res.params.clone();
     for i in 0..res.rows {
         for j in 0..res.cols {
             let res_poly = res.get_poly_mut(i, j);
             let pol2 = a.get_poly(i, j);
-            add_poly_into(params, res_poly, pol2);
+            add_poly_into(&params, res_poly, pol2);
         }
     }
 }
 
 pub fn add_into_at(res: &mut PolyMatrixNTT, a: &PolyMatrixNTT, t_row: usize, t_col: usize) {
-    let params = res.params;
+    let params = res.params.clone();
     for i in 0..a.rows {
         for j in 0..a.cols {
             let res_poly = res.get_poly_mut(t_row + i, t_col + j);
             let pol2 = a.get_poly(i, j);
-            add_poly_into(params, res_poly, pol2);
+            add_poly_into(&params, res_poly, pol2);
         }
     }
 }
@@ -515,39 +568,40 @@ pub fn invert(res: &mut PolyMatrixRaw, a: &PolyMatrixRaw) {
     assert!(res.rows == a.rows);
     assert!(res.cols == a.cols);
 
-    let params = res.params;
+    let params = res.params.clone();
     for i in 0..a.rows {
         for j in 0..a.cols {
             let res_poly = res.get_poly_mut(i, j);
             let pol1 = a.get_poly(i, j);
-            invert_poly(params, res_poly, pol1);
+            invert_poly(&params, res_poly, pol1);
         }
     }
 }
 
-pub fn automorph<'a>(res: &mut PolyMatrixRaw<'a>, a: &PolyMatrixRaw<'a>, t: usize) {
+pub fn automorph(res: &mut PolyMatrixRaw, a: &PolyMatrixRaw, t: usize) {
     assert!(res.rows == a.rows);
     assert!(res.cols == a.cols);
 
-    let params = res.params;
+    let params = res.params.clone();
     for i in 0..a.rows {
         for j in 0..a.cols {
             let res_poly = res.get_poly_mut(i, j);
             let pol1 = a.get_poly(i, j);
-            automorph_poly(params, res_poly, pol1, t);
+            automorph_poly(&params, res_poly, pol1, t);
         }
     }
 }
 
-pub fn automorph_alloc<'a>(a: &PolyMatrixRaw<'a>, t: usize) -> PolyMatrixRaw<'a> {
-    let mut res = PolyMatrixRaw::zero(a.params, a.rows, a.cols);
+pub fn automorph_alloc(a: &PolyMatrixRaw, t: usize) -> PolyMatrixRaw {
+    let mut res = PolyMatrixRaw::zero_secret_if(&a.params, a.rows, a.cols, a.data.is_secret());
     automorph(&mut res, a, t);
     res
 }
 
-pub fn stack<'a>(a: &PolyMatrixRaw<'a>, b: &PolyMatrixRaw<'a>) -> PolyMatrixRaw<'a> {
+pub fn stack(a: &PolyMatrixRaw, b: &PolyMatrixRaw) -> PolyMatrixRaw {
     assert_eq!(a.cols, b.cols);
-    let mut c = PolyMatrixRaw::zero(a.params, a.rows + b.rows, a.cols);
+    let secret = a.data.is_secret() || b.data.is_secret();
+    let mut c = PolyMatrixRaw::zero_secret_if(&a.params, a.rows + b.rows, a.cols, secret);
     c.copy_into(a, 0, 0);
     c.copy_into(b, a.rows, 0);
     c
@@ -557,27 +611,25 @@ pub fn scalar_multiply(res: &mut PolyMatrixNTT, a: &PolyMatrixNTT, b: &PolyMatri
     assert_eq!(a.rows, 1);
     assert_eq!(a.cols, 1);
 
-    let params = res.params;
+    let params = res.params.clone();
     let pol2 = a.get_poly(0, 0);
     for i in 0..b.rows {
         for j in 0..b.cols {
             let res_poly = res.get_poly_mut(i, j);
             let pol1 = b.get_poly(i, j);
-            multiply_poly(params, res_poly, pol1, pol2);
+            multiply_poly(&params, res_poly, pol1, pol2);
         }
     }
 }
 
-pub fn scalar_multiply_alloc<'a>(
-    a: &PolyMatrixNTT<'a>,
-    b: &PolyMatrixNTT<'a>,
-) -> PolyMatrixNTT<'a> {
-    let mut res = PolyMatrixNTT::zero(b.params, b.rows, b.cols);
+pub fn scalar_multiply_alloc(a: &PolyMatrixNTT, b: &PolyMatrixNTT) -> PolyMatrixNTT {
+    let secret = a.data.is_secret() || b.data.is_secret();
+    let mut res = PolyMatrixNTT::zero_secret_if(&b.params, b.rows, b.cols, secret);
     scalar_multiply(&mut res, a, b);
     res
 }
 
-pub fn single_poly<'a>(params: &'a Params, val: u64) -> PolyMatrixRaw<'a> {
+pub fn single_poly(params: &Arc<Params>, val: u64) -> PolyMatrixRaw {
     let mut res = PolyMatrixRaw::zero(params, 1, 1);
     res.get_poly_mut(0, 0)[0] = val;
     res
@@ -592,19 +644,19 @@ fn reduce_copy(params: &Params, out: &mut [u64], inp: &[u64]) {
 }
 
 pub fn to_ntt(a: &mut PolyMatrixNTT, b: &PolyMatrixRaw) {
-    let params = a.params;
+    let params = a.params.clone();
     for r in 0..a.rows {
         for c in 0..a.cols {
             let pol_src = b.get_poly(r, c);
             let pol_dst = a.get_poly_mut(r, c);
-            reduce_copy(params, pol_dst, pol_src);
-            ntt_forward(params, pol_dst);
+            reduce_copy(&params, pol_dst, pol_src);
+            ntt_forward(&params, pol_dst);
         }
     }
 }
 
 pub fn to_ntt_no_reduce(a: &mut PolyMatrixNTT, b: &PolyMatrixRaw) {
-    let params = a.params;
+    let params = a.params.clone();
     for r in 0..a.rows {
         for c in 0..a.cols {
             let pol_src = b.get_poly(r, c);
@@ -613,19 +665,19 @@ pub fn to_ntt_no_reduce(a: &mut PolyMatrixNTT, b: &PolyMatrixRaw) {
                 let idx = n * params.poly_len;
                 pol_dst[idx..idx + params.poly_len].copy_from_slice(pol_src);
             }
-            ntt_forward(params, pol_dst);
+            ntt_forward(&params, pol_dst);
         }
     }
 }
 
-pub fn to_ntt_alloc<'a>(b: &PolyMatrixRaw<'a>) -> PolyMatrixNTT<'a> {
-    let mut a = PolyMatrixNTT::zero(b.params, b.rows, b.cols);
+pub fn to_ntt_alloc(b: &PolyMatrixRaw) -> PolyMatrixNTT {
+    let mut a = PolyMatrixNTT::zero_secret_if(&b.params, b.rows, b.cols, b.data.is_secret());
     to_ntt(&mut a, b);
     a
 }
 
 pub fn from_ntt(a: &mut PolyMatrixRaw, b: &PolyMatrixNTT) {
-    let params = a.params;
+    let params = a.params.clone();
     SCRATCH.with(|scratch_cell| {
         let scratch_vec = &mut *scratch_cell.borrow_mut();
         let scratch = scratch_vec.as_mut_slice();
@@ -634,7 +686,7 @@ pub fn from_ntt(a: &mut PolyMatrixRaw, b: &PolyMatrixNTT) {
                 let pol_src = b.get_poly(r, c);
                 let pol_dst = a.get_poly_mut(r, c);
                 scratch[0..pol_src.len()].copy_from_slice(pol_src);
-                ntt_inverse(params, scratch);
+                ntt_inverse(&params, scratch);
                 for z in 0..params.poly_len {
                     pol_dst[z] = params.crt_compose(scratch, z);
                 }
@@ -643,37 +695,44 @@ pub fn from_ntt(a: &mut PolyMatrixRaw, b: &PolyMatrixNTT) {
     });
 }
 
-pub fn from_ntt_alloc<'a>(b: &PolyMatrixNTT<'a>) -> PolyMatrixRaw<'a> {
-    let mut a = PolyMatrixRaw::zero(b.params, b.rows, b.cols);
+pub fn from_ntt_alloc(b: &PolyMatrixNTT) -> PolyMatrixRaw {
+    let mut a = PolyMatrixRaw::zero_secret_if(&b.params, b.rows, b.cols, b.data.is_secret());
     from_ntt(&mut a, b);
     a
 }
 
-impl<'a, 'b> Neg for &'b PolyMatrixRaw<'a> {
-    type Output = PolyMatrixRaw<'a>;
+impl Neg for &PolyMatrixRaw {
+    type Output = PolyMatrixRaw;
 
     fn neg(self) -> Self::Output {
-        let mut out = PolyMatrixRaw::zero(self.params, self.rows, self.cols);
+        let mut out = PolyMatrixRaw::zero_secret_if(
+            &self.params,
+            self.rows,
+            self.cols,
+            self.data.is_secret(),
+        );
         invert(&mut out, self);
         out
     }
 }
 
-impl<'a, 'b> Mul for &'b PolyMatrixNTT<'a> {
-    type Output = PolyMatrixNTT<'a>;
+impl Mul for &PolyMatrixNTT {
+    type Output = PolyMatrixNTT;
 
     fn mul(self, rhs: Self) -> Self::Output {
-        let mut out = PolyMatrixNTT::zero(self.params, self.rows, rhs.cols);
+        let secret = self.data.is_secret() || rhs.data.is_secret();
+        let mut out = PolyMatrixNTT::zero_secret_if(&self.params, self.rows, rhs.cols, secret);
         multiply(&mut out, self, rhs);
         out
     }
 }
 
-impl<'a, 'b> Add for &'b PolyMatrixNTT<'a> {
-    type Output = PolyMatrixNTT<'a>;
+impl Add for &PolyMatrixNTT {
+    type Output = PolyMatrixNTT;
 
     fn add(self, rhs: Self) -> Self::Output {
-        let mut out = PolyMatrixNTT::zero(self.params, self.rows, self.cols);
+        let secret = self.data.is_secret() || rhs.data.is_secret();
+        let mut out = PolyMatrixNTT::zero_secret_if(&self.params, self.rows, self.cols, secret);
         add(&mut out, self, rhs);
         out
     }
@@ -683,8 +742,8 @@ impl<'a, 'b> Add for &'b PolyMatrixNTT<'a> {
 mod test {
     use super::*;
 
-    fn get_params() -> Params {
-        get_test_params()
+    fn get_params() -> Arc<Params> {
+        Arc::new(get_test_params())
     }
 
     fn assert_all_zero(a: &[u64]) {
@@ -723,6 +782,26 @@ mod test {
         assert_eq!(m3.get_poly(0, 0)[2], 700);
     }
 
+    #[test]
+    fn full_multiply_correctness_for_other_moduli() {
+        let cfg = r#"{"n": 2, "nu_1": 9, "nu_2": 6, "p": 256, "q2_bits": 20, "t_gsw": 8,
+            "t_conv": 4, "t_exp_left": 8, "t_exp_right": 8, "moduli": MODULI}"#;
+        for moduli in ["[72057594037616641]", "[1032193, 974849, 925697]"] {
+            let params = Arc::new(params_from_json(&cfg.replace("MODULI", moduli)));
+            let neg1 = params.modulus - 1;
+            let mut m1 = PolyMatrixRaw::zero(&params, 1, 2);
+            let mut m2 = PolyMatrixRaw::zero(&params, 2, 1);
+            m1.get_poly_mut(0, 0)[1] = neg1;
+            m1.get_poly_mut(0, 1)[params.poly_len - 1] = 3;
+            m2.get_poly_mut(0, 0)[1] = neg1;
+            m2.get_poly_mut(1, 0)[2] = 5;
+            let m3 = from_ntt_alloc(&(&to_ntt_alloc(&m1) * &to_ntt_alloc(&m2)));
+            // x^2 - 15x, since x^(poly_len + 1) = -x
+            assert_eq!(m3.get_poly(0, 0)[1], params.modulus - 15);
+            assert_eq!(m3.get_poly(0, 0)[2], 1);
+        }
+    }
+
     #[test]
     fn to_vec_correctness() {
         let params = get_params();
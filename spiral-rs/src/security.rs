@@ -0,0 +1,220 @@
+use crate::params::*;
+use std::f64::consts::*;
+
+// Core-SVP exponents: one call to an SVP oracle in dimension beta costs 2^(0.292 beta)
+// classically (sieving) and 2^(0.265 beta) quantumly.
+const CLASSICAL_SIEVE: f64 = 0.292;
+const QUANTUM_SIEVE: f64 = 0.265;
+// A sieve outputs about 2^(0.2075 beta) short vectors, which the dual attack reuses.
+const SIEVE_OUTPUT: f64 = 0.2075;
+// The root Hermite factor estimate is only meaningful for larger block sizes.
+const MIN_BETA: usize = 50;
+
+// Core-SVP floor applied by `SecurityPolicy::default()`. The default parameters estimate at
+// about 96 bits here; core-SVP counts a single SVP call, so it sits well below estimates that
+// also cost the BKZ tours around it.
+pub const DEFAULT_MIN_SECURITY_BITS: f64 = 90.;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AttackCost {
+    pub beta: usize,
+    pub samples: usize,
+    pub classical_bits: f64,
+    pub quantum_bits: f64,
+}
+
+impl AttackCost {
+    fn new(beta: usize, samples: usize) -> Self {
+        AttackCost {
+            beta,
+            samples,
+            classical_bits: CLASSICAL_SIEVE * beta as f64,
+            quantum_bits: QUANTUM_SIEVE * beta as f64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SecurityEstimate {
+    pub primal: AttackCost,
+    pub dual: AttackCost,
+}
+
+impl SecurityEstimate {
+    pub fn classical_bits(&self) -> f64 {
+        f64::min(self.primal.classical_bits, self.dual.classical_bits)
+    }
+
+    pub fn quantum_bits(&self) -> f64 {
+        f64::min(self.primal.quantum_bits, self.dual.quantum_bits)
+    }
+}
+
+// Root Hermite factor reached by BKZ with block size beta
+fn log_delta(beta: usize) -> f64 {
+    let b = beta as f64;
+    (f64::ln(PI * b) / b + f64::ln(b / (2. * PI * E))) / (2. * (b - 1.))
+}
+
+// The samples m that make the lattice most favorable to the attacker, given that its
+// dimension is m + n + extra: the exponents below are concave in m, and this is where their
+// derivative vanishes. Public parameters hold many RLWE samples, so up to 2n are allowed.
+fn best_samples(n: usize, extra: usize, log_q: f64, ld: f64) -> usize {
+    let n_f = (n + extra) as f64;
+    let m = f64::sqrt(log_q * n_f / ld) - n_f;
+    m.clamp(1., (2 * n) as f64) as usize
+}
+
+fn find_beta(
+    n: usize,
+    extra: usize,
+    log_q: f64,
+    succeeds: impl Fn(usize, usize) -> bool,
+) -> AttackCost {
+    let max_dim = 3 * n + extra;
+    for beta in MIN_BETA..max_dim {
+        let m = best_samples(n, extra, log_q, log_delta(beta));
+        // m is rounded down, so also try the next integer
+        for m in [m, usize::min(m + 1, 2 * n)] {
+            if beta <= m + n + extra && succeeds(beta, m) {
+                return AttackCost::new(beta, m);
+            }
+        }
+    }
+    AttackCost::new(max_dim, 2 * n)
+}
+
+// Primal attack: solve uSVP in the embedding lattice of dimension d = m + n + 1. BKZ-beta finds
+// the error once the projection of (e, s, 1) onto the last beta coordinates, of length about
+// sigma * sqrt(beta), is shorter than the Gram-Schmidt norm delta^(2 beta - d - 1) q^(m / d).
+fn estimate_primal(n: usize, log_q: f64, sigma: f64) -> AttackCost {
+    find_beta(n, 1, log_q, |beta, m| {
+        let d = (m + n + 1) as f64;
+        let lhs = f64::ln(sigma) + 0.5 * f64::ln(beta as f64);
+        let rhs = (2. * beta as f64 - d - 1.) * log_delta(beta) + (m as f64 / d) * log_q;
+        lhs <= rhs
+    })
+}
+
+// Dual attack: BKZ-beta finds a vector of length l = delta^(d - 1) q^(n / d) in the dual
+// lattice of dimension d = m + n, which distinguishes samples with advantage
+// 4 exp(-2 pi^2 (l sigma / q)^2). One run of the sieve yields enough vectors once the squared
+// advantage is at least 2^(-0.2075 beta).
+fn estimate_dual(n: usize, log_q: f64, sigma: f64) -> AttackCost {
+    find_beta(n, 0, log_q, |beta, m| {
+        let d = (m + n) as f64;
+        let log_l = (d - 1.) * log_delta(beta) + (n as f64 / d) * log_q;
+        let tau = f64::exp(log_l - log_q) * sigma;
+        let log2_eps = 2. - 2. * PI * PI * tau * tau * LOG2_E;
+        log2_eps >= -SIEVE_OUTPUT * beta as f64 / 2.
+    })
+}
+
+// Estimates the core-SVP cost of the primal and dual attacks on RLWE in dimension
+// `poly_len` modulo `modulus`, with secrets and errors drawn from the discrete Gaussian of
+// width `noise_width` (standard deviation noise_width / sqrt(2 pi)).
+pub fn estimate_security(poly_len: usize, modulus: u64, noise_width: f64) -> SecurityEstimate {
+    let log_q = f64::ln(modulus as f64);
+    let sigma = noise_width / f64::sqrt(2. * PI);
+    SecurityEstimate {
+        primal: estimate_primal(poly_len, log_q, sigma),
+        dual: estimate_dual(poly_len, log_q, sigma),
+    }
+}
+
+pub fn estimate_params_security(params: &Params) -> SecurityEstimate {
+    estimate_security(params.poly_len, params.modulus, params.noise_width)
+}
+
+// What loading does with parameters whose classical estimate is below `min_bits`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SecurityPolicy {
+    Allow,
+    Warn { min_bits: f64 },
+    Refuse { min_bits: f64 },
+}
+
+impl Default for SecurityPolicy {
+    fn default() -> Self {
+        SecurityPolicy::Warn {
+            min_bits: DEFAULT_MIN_SECURITY_BITS,
+        }
+    }
+}
+
+impl SecurityPolicy {
+    pub fn check(&self, params: &Params) -> Result<(), ParamsError> {
+        let min_bits = match *self {
+            SecurityPolicy::Allow => return Ok(()),
+            SecurityPolicy::Warn { min_bits } | SecurityPolicy::Refuse { min_bits } => min_bits,
+        };
+        let classical_bits = estimate_params_security(params).classical_bits();
+        if classical_bits >= min_bits {
+            return Ok(());
+        }
+        let err = ParamsError::Insecure {
+            classical_bits,
+            min_bits,
+        };
+        if let SecurityPolicy::Refuse { .. } = self {
+            return Err(err);
+        }
+        eprintln!("warning: {}", err);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::*;
+
+    #[test]
+    fn estimate_security_matches_new_hope() {
+        // NewHope (n = 1024, q = 12289, centered binomial with variance 8) is estimated at
+        // 281 classical and 255 quantum bits with the same methodology.
+        let estimate = estimate_security(1024, 12289, f64::sqrt(16. * PI));
+        assert!((275. ..290.).contains(&estimate.primal.classical_bits));
+        assert!((275. ..290.).contains(&estimate.dual.classical_bits));
+        assert!((250. ..265.).contains(&estimate.quantum_bits()));
+        assert!(estimate.primal.samples < 2 * 1024);
+    }
+
+    #[test]
+    fn estimate_security_is_monotone() {
+        let params = get_test_params();
+        let base = estimate_params_security(&params).classical_bits();
+        assert!(base > DEFAULT_MIN_SECURITY_BITS);
+        assert!(base < 128.);
+
+        let q = params.modulus;
+        let w = params.noise_width;
+        assert!(estimate_security(4096, q, w).classical_bits() > base);
+        assert!(estimate_security(1024, q, w).classical_bits() < base);
+        assert!(estimate_security(2048, q, 2. * w).classical_bits() > base);
+        assert!(estimate_security(2048, q >> 8, w).classical_bits() > base);
+
+        let estimate = estimate_params_security(&params);
+        assert!(estimate.quantum_bits() < estimate.classical_bits());
+    }
+
+    #[test]
+    fn security_policy_refuses_weak_params() {
+        let params = get_test_params();
+        let weak = params_from_json(
+            r#"{"n": 2, "nu_1": 9, "nu_2": 6, "p": 256, "q2_bits": 20, "t_gsw": 8,
+            "t_conv": 4, "t_exp_left": 8, "t_exp_right": 56, "poly_len": 1024}"#,
+        );
+
+        let refuse = SecurityPolicy::Refuse {
+            min_bits: DEFAULT_MIN_SECURITY_BITS,
+        };
+        assert!(refuse.check(&params).is_ok());
+        assert!(matches!(
+            refuse.check(&weak),
+            Err(ParamsError::Insecure { .. })
+        ));
+        assert!(SecurityPolicy::default().check(&weak).is_ok());
+        assert!(SecurityPolicy::Allow.check(&weak).is_ok());
+    }
+}
@@ -1,11 +1,14 @@
 #[cfg(target_feature = "avx2")]
 use std::arch::x86_64::*;
 use std::fs::File;
+use std::io;
 use std::io::BufReader;
+use std::io::Cursor;
 use std::io::Read;
 use std::io::Seek;
 use std::io::SeekFrom;
-use std::cell::RefCell;
+use std::mem::size_of;
+use std::sync::Arc;
 
 use crate::aligned_memory::*;
 use crate::arith::*;
@@ -22,7 +25,7 @@ pub fn coefficient_expansion(
     v: &mut Vec<PolyMatrixNTT>,
     g: usize,
     stop_round: usize,
-    params: &Params,
+    params: &Arc<Params>,
     v_w_left: &Vec<PolyMatrixNTT>,
     v_w_right: &Vec<PolyMatrixNTT>,
     v_neg1: &Vec<PolyMatrixNTT>,
@@ -122,11 +125,11 @@ pub fn coefficient_expansion(
     }
 }
 
-pub fn regev_to_gsw<'a>(
-    v_gsw: &mut Vec<PolyMatrixNTT<'a>>,
-    v_inp: &Vec<PolyMatrixNTT<'a>>,
-    v: &PolyMatrixNTT<'a>,
-    params: &'a Params,
+pub fn regev_to_gsw(
+    v_gsw: &mut Vec<PolyMatrixNTT>,
+    v_inp: &Vec<PolyMatrixNTT>,
+    v: &PolyMatrixNTT,
+    params: &Arc<Params>,
     idx_factor: usize,
     idx_offset: usize,
 ) {
@@ -154,6 +157,9 @@ pub fn regev_to_gsw<'a>(
 
 pub const MAX_SUMMED: usize = 1 << 6;
 pub const PACKED_OFFSET_2: i32 = 32;
+// Products of residues below 2^62 are below 2^124, so a u128 holds this many of them on top
+// of a reduced sum.
+pub const MAX_SUMMED_U128: usize = 15;
 
 #[cfg(target_feature = "avx2")]
 pub fn multiply_reg_by_database(
@@ -164,6 +170,12 @@ pub fn multiply_reg_by_database(
     dim0: usize,
     num_per: usize,
 ) {
+    // the AVX2 path only handles two residues packed at PACKED_OFFSET_2
+    if params.crt_count != 2 {
+        multiply_reg_by_database_scalar(out, db, v_firstdim, params, dim0, num_per);
+        return;
+    }
+
     let ct_rows = 2;
     let ct_cols = 1;
     let pt_rows = 1;
@@ -272,8 +284,8 @@ pub fn multiply_reg_by_database(
     }
 }
 
-#[cfg(not(target_feature = "avx2"))]
-pub fn multiply_reg_by_database(
+// Works for any number of packed residues, including a single modulus above 2^32.
+pub fn multiply_reg_by_database_scalar(
     out: &mut Vec<PolyMatrixNTT>,
     db: &[u64],
     v_firstdim: &[u64],
@@ -286,65 +298,74 @@ pub fn multiply_reg_by_database(
     let pt_rows = 1;
     let pt_cols = 1;
 
-    for z in 0..params.poly_len {
+    // products of small residues are below 2^60, so a u128 never needs reducing early
+    let max_summed = if params.has_small_moduli() {
+        usize::MAX
+    } else {
+        MAX_SUMMED_U128
+    };
+
+    let (crt_count, poly_len) = (params.crt_count, params.poly_len);
+    for z in 0..poly_len {
         let idx_a_base = z * (ct_cols * dim0 * ct_rows);
         let mut idx_b_base = z * (num_per * pt_cols * dim0 * pt_rows);
 
-        for i in 0..num_per {
+        for out_i in out.iter_mut().take(num_per) {
             for c in 0..pt_cols {
-                let mut sums_out_n0_0 = 0u128;
-                let mut sums_out_n0_1 = 0u128;
-                let mut sums_out_n1_0 = 0u128;
-                let mut sums_out_n1_1 = 0u128;
+                let mut sums = [[0u128; 2]; MAX_MODULI];
+                let sums = &mut sums[..crt_count];
 
                 for jm in 0..(dim0 * pt_rows) {
                     let b = db[idx_b_base];
                     idx_b_base += 1;
+                    let v_a = &v_firstdim[idx_a_base + jm * ct_rows..][..ct_rows];
 
-                    let v_a0 = v_firstdim[idx_a_base + jm * ct_rows];
-                    let v_a1 = v_firstdim[idx_a_base + jm * ct_rows + 1];
-
-                    let b_lo = b as u32;
-                    let b_hi = (b >> 32) as u32;
-
-                    let v_a0_lo = v_a0 as u32;
-                    let v_a0_hi = (v_a0 >> 32) as u32;
-
-                    let v_a1_lo = v_a1 as u32;
-                    let v_a1_hi = (v_a1 >> 32) as u32;
-
-                    // do n0
-                    sums_out_n0_0 += ((v_a0_lo as u64) * (b_lo as u64)) as u128;
-                    sums_out_n0_1 += ((v_a1_lo as u64) * (b_lo as u64)) as u128;
+                    for (n, sums_n) in sums.iter_mut().enumerate() {
+                        let b_n = unpack_residue(params, b, n) as u128;
+                        for (sum, &a) in sums_n.iter_mut().zip(v_a) {
+                            *sum += (unpack_residue(params, a, n) as u128) * b_n;
+                        }
+                    }
 
-                    // do n1
-                    sums_out_n1_0 += ((v_a0_hi as u64) * (b_hi as u64)) as u128;
-                    sums_out_n1_1 += ((v_a1_hi as u64) * (b_hi as u64)) as u128;
+                    if (jm + 1) % max_summed == 0 {
+                        for (n, sums_n) in sums.iter_mut().enumerate() {
+                            for sum in sums_n.iter_mut() {
+                                *sum = barrett_coeff_u128(params, *sum, n) as u128;
+                            }
+                        }
+                    }
                 }
 
-                // output n0
-                let (crt_count, poly_len) = (params.crt_count, params.poly_len);
-                let mut n = 0;
-                let mut idx_c = c * (crt_count * poly_len) + n * (poly_len) + z;
-                out[i].data[idx_c] = (sums_out_n0_0 % (params.moduli[0] as u128)) as u64;
-                idx_c += pt_cols * crt_count * poly_len;
-                out[i].data[idx_c] = (sums_out_n0_1 % (params.moduli[0] as u128)) as u64;
-
-                // output n1
-                n = 1;
-                idx_c = c * (crt_count * poly_len) + n * (poly_len) + z;
-                out[i].data[idx_c] = (sums_out_n1_0 % (params.moduli[1] as u128)) as u64;
-                idx_c += pt_cols * crt_count * poly_len;
-                out[i].data[idx_c] = (sums_out_n1_1 % (params.moduli[1] as u128)) as u64;
+                for (n, sums_n) in sums.iter().enumerate() {
+                    for (r, &sum) in sums_n.iter().enumerate() {
+                        let idx_c = r * (pt_cols * crt_count * poly_len)
+                            + c * (crt_count * poly_len)
+                            + n * poly_len
+                            + z;
+                        out_i.data[idx_c] = barrett_coeff_u128(params, sum, n);
+                    }
+                }
             }
         }
     }
 }
 
-pub fn generate_random_db_and_get_item<'a>(
-    params: &'a Params,
+#[cfg(not(target_feature = "avx2"))]
+pub fn multiply_reg_by_database(
+    out: &mut Vec<PolyMatrixNTT>,
+    db: &[u64],
+    v_firstdim: &[u64],
+    params: &Params,
+    dim0: usize,
+    num_per: usize,
+) {
+    multiply_reg_by_database_scalar(out, db, v_firstdim, params, dim0, num_per);
+}
+
+pub fn generate_random_db_and_get_item(
+    params: &Arc<Params>,
     item_idx: usize,
-) -> (PolyMatrixRaw<'a>, AlignedMemory64) {
+) -> (PolyMatrixRaw, AlignedMemory64) {
     let mut rng = get_seeded_rng();
 
     let instances = params.instances;
@@ -386,8 +407,7 @@ pub fn generate_random_db_and_get_item<'a>(
                         &[instances, trials, params.poly_len, num_per, dim0],
                     );
 
-                    v[idx_dst] = db_item_ntt.data[z]
-                        | (db_item_ntt.data[params.poly_len + z] << PACKED_OFFSET_2);
+                    v[idx_dst] = pack_residues(params, db_item_ntt.as_slice(), z);
                 }
             }
         }
@@ -395,13 +415,13 @@ pub fn generate_random_db_and_get_item<'a>(
     (item, v)
 }
 
-pub fn load_item_from_seek<'a, T: Seek + Read + Send + Sync>(
-    params: &'a Params,
+pub fn load_item_from_seek<T: Seek + Read + Send + Sync>(
+    params: &Arc<Params>,
     seekable: &mut T,
     instance: usize,
     trial: usize,
     item_idx: usize,
-) -> PolyMatrixRaw<'a> {
+) -> PolyMatrixRaw {
     let db_item_size = params.db_item_size;
     let instances = params.instances;
     let trials = params.n * params.n;
@@ -438,60 +458,69 @@ pub fn load_item_from_seek<'a, T: Seek + Read + Send + Sync>(
     out
 }
 
-pub fn load_db_from_seek(params: &Params, fname: &String) -> AlignedMemory64 {
+pub fn load_db_from_seek(params: &Arc<Params>, fname: &String) -> AlignedMemory64 {
+    load_db_from_items(params, |instance, trial, i| {
+        let mut file = File::open(fname).unwrap();
+        load_item_from_seek(params, &mut file, instance, trial, i)
+    })
+}
+
+// Preprocesses a database held in memory, laid out like the files `load_db_from_seek` reads
+pub fn load_db_from_bytes(params: &Arc<Params>, data: &[u8]) -> AlignedMemory64 {
+    load_db_from_items(params, |instance, trial, i| {
+        load_item_from_seek(params, &mut Cursor::new(data), instance, trial, i)
+    })
+}
+
+fn load_db_from_items<F>(params: &Arc<Params>, load_item: F) -> AlignedMemory64
+where
+    F: Fn(usize, usize, usize) -> PolyMatrixRaw + Sync,
+{
     let instances = params.instances;
     let trials = params.n * params.n;
     let dim0 = 1 << params.db_dim_1;
     let num_per = 1 << params.db_dim_2;
     let num_items = dim0 * num_per;
     let db_size_words = instances * trials * num_items * params.poly_len;
-    let mut v = AlignedMemory64::new(db_size_words);
+    let v = AlignedMemory64::new(db_size_words);
 
     let total_idx = instances * trials * num_items;
 
-    thread_local!(static STORE: RefCell<Option<File>> = RefCell::new(None));
-
     (0..total_idx).into_par_iter().for_each(|idx| {
-        STORE.with(|cell| {
-            let mut local_store = cell.borrow_mut();
-            if local_store.is_none() {
-                *local_store = Some(File::open(fname).unwrap());
-            }
-
-            let mut indices = [0, 0, 0];
-            decompose_index(&mut indices, idx, &[instances, trials, num_items]);
-            let instance = indices[0];
-            let trial = indices[1];
-            let i = indices[2];
-
-            let ii = i % num_per;
-            let j = i / num_per;
-
-            let mut file = File::open(fname).unwrap();
+        let mut indices = [0, 0, 0];
+        decompose_index(&mut indices, idx, &[instances, trials, num_items]);
+        let instance = indices[0];
+        let trial = indices[1];
+        let i = indices[2];
+
+        let ii = i % num_per;
+        let j = i / num_per;
+
+        // slots past the last item are padding, and stay zero
+        let mut db_item = if i < params.num_items() {
+            load_item(instance, trial, i)
+        } else {
+            PolyMatrixRaw::zero(params, 1, 1)
+        };
+        // db_item.reduce_mod(params.pt_modulus);
 
-            let mut db_item = load_item_from_seek(params, &mut file, instance, trial, i);
-            // db_item.reduce_mod(params.pt_modulus);
+        for z in 0..params.poly_len {
+            db_item.data[z] = recenter_mod(db_item.data[z], params.pt_modulus, params.modulus);
+        }
 
-            for z in 0..params.poly_len {
-                db_item.data[z] =
-                    recenter_mod(db_item.data[z], params.pt_modulus, params.modulus);
-            }
+        let db_item_ntt = db_item.ntt();
+        for z in 0..params.poly_len {
+            let idx_dst = calc_index(
+                &[instance, trial, z, ii, j],
+                &[instances, trials, params.poly_len, num_per, dim0],
+            );
 
-            let db_item_ntt = db_item.ntt();
-            for z in 0..params.poly_len {
-                let idx_dst = calc_index(
-                    &[instance, trial, z, ii, j],
-                    &[instances, trials, params.poly_len, num_per, dim0],
-                );
+            let val = pack_residues(params, db_item_ntt.as_slice(), z);
 
-                let val = db_item_ntt.data[z]
-                    | (db_item_ntt.data[params.poly_len + z] << PACKED_OFFSET_2);
-                    
-                unsafe {
-                    *(v.as_ptr() as *mut u64).offset(idx_dst as isize) = val;
-                }
+            unsafe {
+                *(v.as_ptr() as *mut u64).offset(idx_dst as isize) = val;
             }
-        });
+        }
     });
     v
 }
@@ -510,23 +539,38 @@ pub fn load_file(data: &mut [u64], file: &mut File) {
     }
 }
 
-pub fn load_preprocessed_db_from_file(params: &Params, file: &mut File) -> AlignedMemory64 {
+// Fails if the file is not exactly the size of a database preprocessed under `params`
+pub fn load_preprocessed_db_from_file(
+    params: &Params,
+    file: &mut File,
+) -> io::Result<AlignedMemory64> {
     let instances = params.instances;
     let trials = params.n * params.n;
     let dim0 = 1 << params.db_dim_1;
     let num_per = 1 << params.db_dim_2;
     let num_items = dim0 * num_per;
     let db_size_words = instances * trials * num_items * params.poly_len;
-    let mut v = AlignedMemory64::new(db_size_words);
-    let v_mut_slice = v.as_mut_slice();
 
-    load_file(v_mut_slice, file);
+    let file_len = file.metadata()?.len();
+    let db_len = (db_size_words * size_of::<u64>()) as u64;
+    if file_len != db_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "the database file is {} bytes, but these parameters need {}",
+                file_len, db_len
+            ),
+        ));
+    }
 
-    v
+    let mut v = AlignedMemory64::new(db_size_words);
+    file.read_exact(unsafe { v.as_mut_slice().align_to_mut::<u8>().1 })?;
+
+    Ok(v)
 }
 
 pub fn fold_ciphertexts(
-    params: &Params,
+    params: &Arc<Params>,
     v_cts: &mut Vec<PolyMatrixRaw>,
     v_folding: &Vec<PolyMatrixNTT>,
     v_folding_neg: &Vec<PolyMatrixNTT>,
@@ -537,10 +581,10 @@ pub fn fold_ciphertexts(
 
     let further_dims = log2(v_cts.len() as u64) as usize;
     let ell = v_folding[0].cols / 2;
-    let mut ginv_c = PolyMatrixRaw::zero(&params, 2 * ell, 1);
-    let mut ginv_c_ntt = PolyMatrixNTT::zero(&params, 2 * ell, 1);
-    let mut prod = PolyMatrixNTT::zero(&params, 2, 1);
-    let mut sum = PolyMatrixNTT::zero(&params, 2, 1);
+    let mut ginv_c = PolyMatrixRaw::zero(params, 2 * ell, 1);
+    let mut ginv_c_ntt = PolyMatrixNTT::zero(params, 2 * ell, 1);
+    let mut prod = PolyMatrixNTT::zero(params, 2, 1);
+    let mut sum = PolyMatrixNTT::zero(params, 2, 1);
 
     let mut num_per = v_cts.len();
     for cur_dim in 0..further_dims {
@@ -566,11 +610,11 @@ pub fn fold_ciphertexts(
     }
 }
 
-pub fn pack<'a>(
-    params: &'a Params,
+pub fn pack(
+    params: &Arc<Params>,
     v_ct: &Vec<PolyMatrixRaw>,
     v_w: &Vec<PolyMatrixNTT>,
-) -> PolyMatrixNTT<'a> {
+) -> PolyMatrixNTT {
     assert!(v_ct.len() >= params.n * params.n);
     assert!(v_w.len() == params.n);
     assert!(v_ct[0].rows == 2);
@@ -610,17 +654,12 @@ pub fn pack<'a>(
 pub fn encode(params: &Params, v_packed_ct: &Vec<PolyMatrixRaw>) -> Vec<u8> {
     let q1 = 4 * params.pt_modulus;
     let q1_bits = log2_ceil(q1) as usize;
-    let q2 = Q2_VALUES[params.q2_bits as usize];
+    let q2 = params.q2;
     let q2_bits = params.q2_bits as usize;
 
-    let num_bits = params.instances
-        * ((q2_bits * params.n * params.poly_len)
-            + (q1_bits * params.n * params.n * params.poly_len));
-    let round_to = 64;
-    let num_bytes_rounded_up = ((num_bits + round_to - 1) / round_to) * round_to / 8;
-
-    let mut result = vec![0u8; num_bytes_rounded_up];
-    let mut bit_offs = 0;
+    let mut result = vec![0u8; params.response_bytes()];
+    result[..FINGERPRINT_LENGTH].copy_from_slice(&params.fingerprint().to_le_bytes());
+    let mut bit_offs = FINGERPRINT_LENGTH * 8;
     for instance in 0..params.instances {
         let packed_ct = &v_packed_ct[instance];
 
@@ -642,10 +681,10 @@ pub fn encode(params: &Params, v_packed_ct: &Vec<PolyMatrixRaw>) -> Vec<u8> {
     result
 }
 
-pub fn get_v_folding_neg<'a>(
-    params: &'a Params,
-    v_folding: &Vec<PolyMatrixNTT<'a>>,
-) -> Vec<PolyMatrixNTT<'a>> {
+pub fn get_v_folding_neg(
+    params: &Arc<Params>,
+    v_folding: &Vec<PolyMatrixNTT>,
+) -> Vec<PolyMatrixNTT> {
     let gadget_ntt = build_gadget(params, 2, 2 * params.t_gsw).ntt(); // TODO: make this better
 
     let v_folding_neg = (0..params.db_dim_2)
@@ -662,11 +701,11 @@ pub fn get_v_folding_neg<'a>(
     v_folding_neg
 }
 
-pub fn expand_query<'a>(
-    params: &'a Params,
-    public_params: &PublicParameters<'a>,
-    query: &Query<'a>,
-) -> (AlignedMemory64, Vec<PolyMatrixNTT<'a>>) {
+pub fn expand_query(
+    params: &Arc<Params>,
+    public_params: &PublicParameters,
+    query: &Query,
+) -> (AlignedMemory64, Vec<PolyMatrixNTT>) {
     let dim0 = 1 << params.db_dim_1;
     let further_dims = params.db_dim_2;
 
@@ -731,7 +770,7 @@ pub fn expand_query<'a>(
 }
 
 pub fn process_query(
-    params: &Params,
+    params: &Arc<Params>,
     public_params: &PublicParameters,
     query: &Query,
     db: &[u64],
@@ -809,21 +848,17 @@ pub fn process_query(
 mod test {
     use super::*;
     use crate::client::*;
-    use rand::{Rng, SeedableRng};
+    use crate::error::*;
+    use rand::{Rng, RngCore, SeedableRng};
     use rand_chacha::ChaCha20Rng;
 
     const TEST_PREPROCESSED_DB_PATH: &'static str = "/home/samir/wiki/enwiki-20220320.dbp";
 
-    fn get_params() -> Params {
-        get_fast_expansion_testing_params()
+    fn get_params() -> Arc<Params> {
+        Arc::new(get_fast_expansion_testing_params())
     }
 
-    fn dec_reg<'a>(
-        params: &'a Params,
-        ct: &PolyMatrixNTT<'a>,
-        client: &mut Client<'a>,
-        scale_k: u64,
-    ) -> u64 {
+    fn dec_reg(params: &Arc<Params>, ct: &PolyMatrixNTT, client: &mut Client, scale_k: u64) -> u64 {
         let dec = client.decrypt_matrix_reg(ct).raw();
         let mut val = dec.data[0] as i64;
         if val >= (params.modulus / 2) as i64 {
@@ -837,7 +872,7 @@ mod test {
         }
     }
 
-    fn dec_gsw<'a>(params: &'a Params, ct: &PolyMatrixNTT<'a>, client: &mut Client<'a>) -> u64 {
+    fn dec_gsw(params: &Arc<Params>, ct: &PolyMatrixNTT, client: &mut Client) -> u64 {
         let dec = client.decrypt_matrix_reg(ct).raw();
         let idx = 2 * (params.t_gsw - 1) * params.poly_len + params.poly_len; // this offset should encode a large value
         let mut val = dec.data[idx] as i64;
@@ -899,7 +934,7 @@ mod test {
     #[test]
     fn regev_to_gsw_is_correct() {
         let mut params = get_params();
-        params.db_dim_2 = 1;
+        Arc::make_mut(&mut params).db_dim_2 = 1;
         let mut rng = ChaCha20Rng::from_entropy();
         let mut rng_pub = ChaCha20Rng::from_entropy();
         let mut client = Client::init(&params);
@@ -991,6 +1026,36 @@ mod test {
         }
     }
 
+    #[test]
+    fn multiply_reg_by_database_scalar_matches() {
+        let params = get_params();
+        let dim0 = 1 << params.db_dim_1;
+        let num_per = 1 << params.db_dim_2;
+
+        let (_, db) = generate_random_db_and_get_item(&params, 0);
+        let v_reg: Vec<_> = (0..dim0)
+            .map(|_| PolyMatrixNTT::random(&params, 2, 1))
+            .collect();
+        let mut v_reg_reoriented = AlignedMemory64::new(dim0 * 2 * params.poly_len);
+        reorient_reg_ciphertexts(&params, v_reg_reoriented.as_mut_slice(), &v_reg);
+
+        let mut out = vec![PolyMatrixNTT::zero(&params, 2, 1); num_per];
+        let mut out_scalar = out.clone();
+        let v_firstdim = v_reg_reoriented.as_slice();
+        multiply_reg_by_database(&mut out, db.as_slice(), v_firstdim, &params, dim0, num_per);
+        multiply_reg_by_database_scalar(
+            &mut out_scalar,
+            db.as_slice(),
+            v_firstdim,
+            &params,
+            dim0,
+            num_per,
+        );
+        for i in 0..num_per {
+            assert_eq!(out[i].as_slice(), out_scalar[i].as_slice());
+        }
+    }
+
     #[test]
     fn fold_ciphertexts_is_correct() {
         let params = get_params();
@@ -1059,7 +1124,7 @@ mod test {
         );
     }
 
-    fn full_protocol_is_correct_for_params(params: &Params) {
+    fn full_protocol_is_correct_for_params(params: &Arc<Params>) {
         let mut seeded_rng = get_seeded_rng();
 
         let target_idx = seeded_rng.gen::<usize>() % (params.db_dim_1 + params.db_dim_2);
@@ -1067,13 +1132,13 @@ mod test {
         let mut client = Client::init(&params);
 
         let public_params = client.generate_keys();
-        let query = client.generate_query(target_idx);
+        let query = client.generate_query(target_idx).unwrap();
 
         let (corr_item, db) = generate_random_db_and_get_item(params, target_idx);
 
         let response = process_query(params, &public_params, &query, db.as_slice());
 
-        let result = client.decode_response(response.as_slice());
+        let result = client.decode_response(response.as_slice()).unwrap();
 
         let p_bits = log2_ceil(params.pt_modulus) as usize;
         let corr_result = corr_item.to_vec(p_bits, params.modp_words_per_chunk());
@@ -1085,7 +1150,163 @@ mod test {
         }
     }
 
-    fn full_protocol_is_correct_for_params_real_db(params: &Params) {
+    #[test]
+    fn restored_client_decodes_responses() {
+        let params = get_params();
+        let mut client = Client::init(&params);
+        let public_params = client.generate_keys();
+        let secret_key = client.export_secret_key();
+        drop(client);
+
+        // a restored client keeps using the public parameters uploaded before
+        let client = Client::from_secret_key(&params, &secret_key).unwrap();
+        let (corr_item, db) = generate_random_db_and_get_item(&params, 3);
+        let query = client.generate_query(3).unwrap();
+        let response = process_query(&params, &public_params, &query, db.as_slice());
+        let result = client.decode_response(response.as_slice()).unwrap();
+
+        let p_bits = log2_ceil(params.pt_modulus) as usize;
+        assert_eq!(
+            result,
+            corr_item.to_vec(p_bits, params.modp_words_per_chunk())
+        );
+    }
+
+    #[test]
+    fn preprocessed_db_file_length_is_checked() {
+        let params = Arc::new(get_fast_expansion_testing_params());
+        let db_len = params.instances
+            * params.n
+            * params.n
+            * params.num_slots()
+            * params.poly_len
+            * size_of::<u64>();
+        let path = std::env::temp_dir().join(format!("spiral-db-{}.dbp", std::process::id()));
+
+        let mut data = vec![0u8; db_len];
+        get_seeded_rng().fill_bytes(&mut data);
+        std::fs::write(&path, &data).unwrap();
+        let db = load_preprocessed_db_from_file(&params, &mut File::open(&path).unwrap()).unwrap();
+        assert_eq!(unsafe { db.as_slice().align_to::<u8>().1 }, &data[..]);
+
+        for len in [0, db_len - 8, db_len + 8] {
+            data.resize(len, 0);
+            std::fs::write(&path, &data).unwrap();
+            let mut file = File::open(&path).unwrap();
+            assert!(load_preprocessed_db_from_file(&params, &mut file).is_err());
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn padding_slots_load_as_zero() {
+        let mut cfg = ParamsConfig::from(&*get_params());
+        cfg.num_items = Some(3);
+        let params = Arc::new(Params::try_from(cfg).unwrap());
+
+        let mut data = vec![0u8; params.num_slots() * params.db_item_size];
+        get_seeded_rng().fill_bytes(&mut data[..3 * params.db_item_size]);
+        let db = load_db_from_bytes(&params, &data);
+
+        // whatever follows the last item is never read
+        get_seeded_rng().fill_bytes(&mut data[3 * params.db_item_size..]);
+        assert_eq!(load_db_from_bytes(&params, &data).as_slice(), db.as_slice());
+        assert_eq!(
+            load_db_from_bytes(&params, &data[..3 * params.db_item_size]).as_slice(),
+            db.as_slice()
+        );
+    }
+
+    #[test]
+    fn batch_queries_are_correct() {
+        let params = get_params();
+        let mut client = Client::init(&params);
+        let public_params = client.generate_keys();
+        let idx_targets = [3, 0, 3, 7];
+
+        // the batch draws from the rng exactly as single queries would
+        let seed = [1u8; SEED_LENGTH];
+        let batch = client
+            .generate_queries_rng(&idx_targets, &mut ChaCha20Rng::from_seed(seed))
+            .unwrap();
+        let mut rng = ChaCha20Rng::from_seed(seed);
+        for (&idx_target, query) in idx_targets.iter().zip(batch.iter()) {
+            let single = client.generate_query_rng(idx_target, &mut rng).unwrap();
+            assert_eq!(query.serialize(), single.serialize());
+        }
+
+        let queries = client.generate_queries(&idx_targets).unwrap();
+        let mut responses = Vec::new();
+        let mut corr_items = Vec::new();
+        for (&idx_target, query) in idx_targets.iter().zip(queries.iter()) {
+            let (corr_item, db) = generate_random_db_and_get_item(&params, idx_target);
+            responses.push(process_query(&params, &public_params, query, db.as_slice()));
+            corr_items.push(corr_item);
+        }
+        let results = client.decode_responses(&responses).unwrap();
+
+        let p_bits = log2_ceil(params.pt_modulus) as usize;
+        assert_eq!(results.len(), idx_targets.len());
+        assert_eq!(results[0], client.decode_response(&responses[0]).unwrap());
+        for (result, corr_item) in results.iter().zip(corr_items.iter()) {
+            assert_eq!(
+                *result,
+                corr_item.to_vec(p_bits, params.modp_words_per_chunk())
+            );
+        }
+    }
+
+    #[test]
+    fn decoding_noise_flags_bad_responses() {
+        let params = get_params();
+        let mut client = Client::init(&params);
+        let public_params = client.generate_keys();
+        let (corr_item, db) = generate_random_db_and_get_item(&params, 2);
+        let query = client.generate_query(2).unwrap();
+        let response = process_query(&params, &public_params, &query, db.as_slice());
+
+        let (result, noise) = client.decode_response_with_noise(&response).unwrap();
+        let p_bits = log2_ceil(params.pt_modulus) as usize;
+        assert_eq!(
+            result,
+            corr_item.to_vec(p_bits, params.modp_words_per_chunk())
+        );
+        assert_eq!(
+            noise.noise.len(),
+            params.instances * params.n * params.n * params.poly_len
+        );
+        assert!(!noise.is_suspect());
+
+        // under the wrong key a response decodes to noise spread over the whole range
+        let mut other = Client::init(&params);
+        other.generate_secret_keys();
+        let (_, noise) = other.decode_response_with_noise(&response).unwrap();
+        assert!(noise.is_suspect());
+        assert!(noise.max() < noise.threshold);
+    }
+
+    // The test params above are far from the noise bound; on this production config with its
+    // full database, honest responses peak near 0.3 of the threshold
+    #[test]
+    #[ignore]
+    fn decoding_noise_of_honest_responses_is_low_20_256() {
+        let params = Arc::new(params_from_json(&CFG_20_256.replace("'", "\"")));
+        let mut client = Client::init(&params);
+        let public_params = client.generate_keys();
+        let (corr_item, db) = generate_random_db_and_get_item(&params, 1234);
+        let query = client.generate_query(1234).unwrap();
+        let response = process_query(&params, &public_params, &query, db.as_slice());
+
+        let (result, noise) = client.decode_response_with_noise(&response).unwrap();
+        let p_bits = log2_ceil(params.pt_modulus) as usize;
+        assert_eq!(
+            result,
+            corr_item.to_vec(p_bits, params.modp_words_per_chunk())
+        );
+        assert!(!noise.is_suspect());
+    }
+
+    fn full_protocol_is_correct_for_params_real_db(params: &Arc<Params>) {
         let mut seeded_rng = get_seeded_rng();
 
         let target_idx = seeded_rng.gen::<usize>() % (params.db_dim_1 + params.db_dim_2);
@@ -1093,15 +1314,15 @@ mod test {
         let mut client = Client::init(&params);
 
         let public_params = client.generate_keys();
-        let query = client.generate_query(target_idx);
+        let query = client.generate_query(target_idx).unwrap();
 
         let mut file = File::open(TEST_PREPROCESSED_DB_PATH).unwrap();
 
-        let db = load_preprocessed_db_from_file(params, &mut file);
+        let db = load_preprocessed_db_from_file(params, &mut file).unwrap();
 
         let response = process_query(params, &public_params, &query, db.as_slice());
 
-        let result = client.decode_response(response.as_slice());
+        let result = client.decode_response(response.as_slice()).unwrap();
 
         let corr_result = vec![0x42, 0x5a, 0x68];
 
@@ -1115,6 +1336,65 @@ mod test {
         full_protocol_is_correct_for_params(&get_params());
     }
 
+    #[test]
+    fn full_protocol_is_correct_for_other_rings() {
+        for (poly_len, moduli) in [
+            (1024, "[268369921, 249561089]"),
+            (4096, "[268361729, 268271617]"),
+        ] {
+            let cfg = format!(
+                r#"{{"n": 2, "nu_1": 6, "nu_2": 2, "p": 256, "q2_bits": 20, "t_gsw": 8,
+                "t_conv": 4, "t_exp_left": 8, "t_exp_right": 8, "poly_len": {},
+                "moduli": {}}}"#,
+                poly_len, moduli
+            );
+            full_protocol_is_correct_for_params(&Arc::new(params_from_json(&cfg)));
+        }
+    }
+
+    #[test]
+    fn full_protocol_is_correct_for_other_crt_counts() {
+        for moduli in ["[72057594037616641]", "[1032193, 974849, 925697]"] {
+            let cfg = format!(
+                r#"{{"n": 2, "nu_1": 6, "nu_2": 2, "p": 256, "q2_bits": 20, "t_gsw": 8,
+                "t_conv": 4, "t_exp_left": 8, "t_exp_right": 8, "moduli": {}}}"#,
+                moduli
+            );
+            full_protocol_is_correct_for_params(&Arc::new(params_from_json(&cfg)));
+
+            let cfg = format!(
+                r#"{{"direct_upload": 1, "n": 2, "nu_1": 6, "nu_2": 2, "p": 256,
+                "q2_bits": 20, "t_gsw": 8, "t_conv": 4, "t_exp_left": 8, "t_exp_right": 8,
+                "moduli": {}}}"#,
+                moduli
+            );
+            full_protocol_is_correct_for_params(&Arc::new(params_from_json(&cfg)));
+        }
+    }
+
+    #[test]
+    fn decode_response_rejects_other_params() {
+        let params = get_params();
+        let mut other_params = get_params();
+        Arc::make_mut(&mut other_params).t_exp_right += 1;
+
+        let mut client = Client::init(&params);
+        let public_params = client.generate_keys();
+        let query = client.generate_query(0).unwrap();
+        let (_, db) = generate_random_db_and_get_item(&params, 0);
+        let response = process_query(&params, &public_params, &query, db.as_slice());
+
+        let other_client = Client::init(&other_params);
+        let err = other_client
+            .decode_response(response.as_slice())
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            SpiralError::WrongParams { expected, found }
+                if expected == other_params.fingerprint() && found == params.fingerprint()
+        ));
+    }
+
     #[test]
     #[ignore]
     fn larger_full_protocol_is_correct() {
@@ -1135,7 +1415,7 @@ mod test {
         "#;
         let cfg = cfg_expand;
         let cfg = cfg.replace("'", "\"");
-        let params = params_from_json(&cfg);
+        let params = Arc::new(params_from_json(&cfg));
 
         full_protocol_is_correct_for_params(&params);
         full_protocol_is_correct_for_params_real_db(&params);
@@ -1154,8 +1434,8 @@ mod test {
     #[test]
     #[ignore]
     fn full_protocol_is_correct_real_db_16_100000() {
-        full_protocol_is_correct_for_params_real_db(&params_from_json(
+        full_protocol_is_correct_for_params_real_db(&Arc::new(params_from_json(
             &CFG_16_100000.replace("'", "\""),
-        ));
+        )));
     }
 }
@@ -0,0 +1,421 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+use crate::{cache::*, client::PublicParameters, params::Params};
+
+const PUB_PARAMS_EXTENSION: &str = "pp";
+const TEMP_EXTENSION: &str = "tmp";
+const MAX_ID_LENGTH: usize = 128;
+
+// Where a server keeps the public parameters that clients set up, under the id it hands
+// back to them. Each set of parameters expires some time after it is inserted.
+pub trait PublicParamsStore: Send + Sync {
+    fn insert(&self, id: &str, pub_params: PublicParameters) -> io::Result<()>;
+
+    fn get(&self, id: &str) -> Option<Arc<PublicParameters>>;
+
+    fn remaining_lifetime(&self, id: &str) -> Option<Duration>;
+}
+
+// Ids name files, so they are limited to characters that cannot leave the directory
+pub fn is_valid_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_ID_LENGTH
+        && id.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+}
+
+fn over_budget_err() -> io::Error {
+    io::Error::other("the budget is smaller than one set of public parameters")
+}
+
+// Keeps public parameters in memory only, evicting the least recently used past `max_bytes`
+pub struct MemoryPublicParamsStore {
+    params: Arc<Params>,
+    cache: Mutex<LruTtlCache<Arc<PublicParameters>>>,
+}
+
+impl MemoryPublicParamsStore {
+    pub fn new(params: &Arc<Params>, max_bytes: usize, ttl: Duration) -> Self {
+        Self {
+            params: params.clone(),
+            cache: Mutex::new(LruTtlCache::new(max_bytes, ttl)),
+        }
+    }
+}
+
+impl PublicParamsStore for MemoryPublicParamsStore {
+    fn insert(&self, id: &str, pub_params: PublicParameters) -> io::Result<()> {
+        let size_bytes = self.params.setup_bytes();
+        let mut cache = self.cache.lock().unwrap();
+        if !cache.insert(id.to_string(), Arc::new(pub_params), size_bytes) {
+            return Err(over_budget_err());
+        }
+        Ok(())
+    }
+
+    fn get(&self, id: &str) -> Option<Arc<PublicParameters>> {
+        self.cache.lock().unwrap().get(id)
+    }
+
+    fn remaining_lifetime(&self, id: &str) -> Option<Duration> {
+        self.cache.lock().unwrap().remaining_lifetime(id)
+    }
+}
+
+// Keeps serialized public parameters in `dir`, one file per id, so they outlive the process.
+// Parameters are deserialized the first time they are used and then held in memory like
+// `MemoryPublicParamsStore` does. A file expires `ttl` after it was written, and the least
+// recently used files are deleted once the directory holds more than `max_disk_bytes`.
+pub struct FilePublicParamsStore {
+    dir: PathBuf,
+    params: Arc<Params>,
+    ttl: Duration,
+    cache: Mutex<LruTtlCache<Arc<PublicParameters>>>,
+    // every file in `dir`, by id and size
+    files: Mutex<LruTtlCache<()>>,
+    temp_files: AtomicU64,
+}
+
+impl FilePublicParamsStore {
+    // Opens `dir`, creating it if needed, and removes the files there that have expired, were
+    // left half-written, or do not fit in `max_disk_bytes`
+    pub fn open(
+        dir: impl AsRef<Path>,
+        params: &Arc<Params>,
+        max_bytes: usize,
+        max_disk_bytes: usize,
+        ttl: Duration,
+    ) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let store = Self {
+            dir,
+            params: params.clone(),
+            ttl,
+            cache: Mutex::new(LruTtlCache::new(max_bytes, ttl)),
+            files: Mutex::new(LruTtlCache::new(max_disk_bytes, ttl)),
+            temp_files: AtomicU64::new(0),
+        };
+
+        let mut found = Vec::new();
+        for entry in fs::read_dir(&store.dir)? {
+            let path = entry?.path();
+            match path.extension().and_then(|x| x.to_str()) {
+                Some(PUB_PARAMS_EXTENSION) => found.push(path),
+                Some(TEMP_EXTENSION) => fs::remove_file(&path)?,
+                _ => {}
+            }
+        }
+
+        // the oldest files are the first to go if they do not all fit
+        let mut live = Vec::new();
+        for path in found {
+            let id = path.file_stem().and_then(|x| x.to_str()).unwrap_or("");
+            let metadata = fs::metadata(&path)?;
+            match store.file_lifetime(&metadata) {
+                Some(lifetime) if is_valid_id(id) => {
+                    live.push((lifetime, id.to_string(), metadata.len() as usize))
+                }
+                _ => fs::remove_file(&path)?,
+            }
+        }
+        live.sort();
+        let mut files = store.files.lock().unwrap();
+        for (lifetime, id, size_bytes) in live {
+            match files.insert_evicting(id.clone(), (), size_bytes, lifetime) {
+                Some(evicted) => store.remove_files(&evicted),
+                None => store.remove_files(&[id]),
+            }
+        }
+        drop(files);
+        Ok(store)
+    }
+
+    fn path(&self, id: &str) -> Option<PathBuf> {
+        if !is_valid_id(id) {
+            return None;
+        }
+        Some(self.dir.join(format!("{}.{}", id, PUB_PARAMS_EXTENSION)))
+    }
+
+    // How long a file last written at `metadata`'s modification time has left
+    fn file_lifetime(&self, metadata: &fs::Metadata) -> Option<Duration> {
+        let written = metadata.modified().ok()?;
+        let age = SystemTime::now()
+            .duration_since(written)
+            .unwrap_or_default();
+        self.ttl.checked_sub(age).filter(|x| !x.is_zero())
+    }
+
+    fn remove_files(&self, ids: &[String]) {
+        for id in ids {
+            if let Some(path) = self.path(id) {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+}
+
+impl PublicParamsStore for FilePublicParamsStore {
+    fn insert(&self, id: &str, pub_params: PublicParameters) -> io::Result<()> {
+        let path = self.path(id).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("invalid id {:?}", id))
+        })?;
+        let data = pub_params.serialize();
+        let pub_params = Arc::new(pub_params);
+
+        // both budgets are checked before anything is written
+        let size_bytes = self.params.setup_bytes();
+        if !self
+            .cache
+            .lock()
+            .unwrap()
+            .insert(id.to_string(), pub_params, size_bytes)
+        {
+            return Err(over_budget_err());
+        }
+        // held until the file is in place, so that no other insert evicts it half-written
+        let mut files = self.files.lock().unwrap();
+        let evicted = match files.insert_evicting(id.to_string(), (), data.len(), self.ttl) {
+            Some(evicted) => evicted,
+            None => {
+                self.cache.lock().unwrap().remove(id);
+                return Err(over_budget_err());
+            }
+        };
+        self.remove_files(&evicted);
+
+        // write under a name of our own, so that concurrent setups never see a partial file
+        let temp_file = self.temp_files.fetch_add(1, Ordering::Relaxed);
+        let temp_path = self
+            .dir
+            .join(format!("{}.{}.{}", id, temp_file, TEMP_EXTENSION));
+        let written = fs::write(&temp_path, &data).and_then(|_| fs::rename(&temp_path, &path));
+        if written.is_err() {
+            let _ = fs::remove_file(&temp_path);
+            files.remove(id);
+            self.cache.lock().unwrap().remove(id);
+        }
+        written
+    }
+
+    fn get(&self, id: &str) -> Option<Arc<PublicParameters>> {
+        // a file the index no longer holds has expired or been evicted, even if the memory
+        // copy has not
+        // files are only removed under the lock, so that none is taken from a concurrent insert
+        let path = self.path(id)?;
+        let mut files = self.files.lock().unwrap();
+        files.get(id);
+        let lifetime = match files.remaining_lifetime(id) {
+            Some(lifetime) => lifetime,
+            None => {
+                let _ = fs::remove_file(&path);
+                self.cache.lock().unwrap().remove(id);
+                return None;
+            }
+        };
+        drop(files);
+        if let Some(pub_params) = self.cache.lock().unwrap().get(id) {
+            return Some(pub_params);
+        }
+
+        let data = fs::read(&path).ok()?;
+        let pub_params = match PublicParameters::deserialize(&self.params, &data) {
+            Ok(pub_params) => Arc::new(pub_params),
+            Err(_) => {
+                // written under other parameters, so no query can use it
+                let mut files = self.files.lock().unwrap();
+                files.remove(id);
+                let _ = fs::remove_file(&path);
+                return None;
+            }
+        };
+
+        let size_bytes = self.params.setup_bytes();
+        let mut cache = self.cache.lock().unwrap();
+        cache.insert_with_ttl(id.to_string(), pub_params.clone(), size_bytes, lifetime);
+        Some(pub_params)
+    }
+
+    fn remaining_lifetime(&self, id: &str) -> Option<Duration> {
+        self.files.lock().unwrap().remaining_lifetime(id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{client::*, util::*};
+
+    const TTL: Duration = Duration::from_secs(60);
+    const DISK_BYTES: usize = 1 << 30;
+
+    fn get_params() -> Arc<Params> {
+        Arc::new(get_short_keygen_params())
+    }
+
+    fn get_pub_params(params: &Arc<Params>) -> PublicParameters {
+        Client::init(params).generate_keys()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("spiral-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn ids_are_checked() {
+        assert!(is_valid_id("0f3c2a9e-5b1d-4c8e-9a7f-6d2e1b0c4a8f"));
+        assert!(is_valid_id("0123456789abcdef"));
+        for id in [
+            "",
+            "../x",
+            "a/b",
+            "a.pp",
+            "a b",
+            &"a".repeat(MAX_ID_LENGTH + 1),
+        ] {
+            assert!(!is_valid_id(id));
+        }
+    }
+
+    #[test]
+    fn memory_store_is_correct() {
+        let params = get_params();
+        let pub_params = get_pub_params(&params);
+        let data = pub_params.serialize();
+
+        let store = MemoryPublicParamsStore::new(&params, 2 * params.setup_bytes(), TTL);
+        store.insert("a", pub_params).unwrap();
+        assert_eq!(store.get("a").unwrap().serialize(), data);
+        assert!(store.remaining_lifetime("a").unwrap() <= TTL);
+        assert!(store.get("b").is_none());
+        assert!(store.remaining_lifetime("b").is_none());
+
+        let store = MemoryPublicParamsStore::new(&params, params.setup_bytes() - 1, TTL);
+        assert!(store.insert("a", get_pub_params(&params)).is_err());
+    }
+
+    #[test]
+    fn file_store_outlives_the_process() {
+        let params = get_params();
+        let pub_params = get_pub_params(&params);
+        let data = pub_params.serialize();
+        let dir = temp_dir("file-store");
+
+        let store =
+            FilePublicParamsStore::open(&dir, &params, params.setup_bytes(), DISK_BYTES, TTL)
+                .unwrap();
+        store.insert("a", pub_params).unwrap();
+        assert!(store.insert("../a", get_pub_params(&params)).is_err());
+        assert_eq!(store.get("a").unwrap().serialize(), data);
+        drop(store);
+
+        // a new store only reads the file once the parameters are used
+        fs::write(dir.join("b.0.tmp"), b"partial").unwrap();
+        let store =
+            FilePublicParamsStore::open(&dir, &params, params.setup_bytes(), DISK_BYTES, TTL)
+                .unwrap();
+        assert!(!dir.join("b.0.tmp").exists());
+        assert!(store.cache.lock().unwrap().is_empty());
+        assert!(store.remaining_lifetime("a").unwrap() <= TTL);
+        assert_eq!(store.get("a").unwrap().serialize(), data);
+        assert!(!store.cache.lock().unwrap().is_empty());
+        assert!(store.get("b").is_none());
+        assert!(store.get("../a").is_none());
+
+        // files written under other parameters are dropped
+        let other = Arc::new(get_expansion_testing_params());
+        let store = FilePublicParamsStore::open(&dir, &other, other.setup_bytes(), DISK_BYTES, TTL)
+            .unwrap();
+        assert!(store.get("a").is_none());
+        assert!(!dir.join("a.pp").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn file_store_expires_files() {
+        let params = get_params();
+        let dir = temp_dir("file-store-expiry");
+
+        let store =
+            FilePublicParamsStore::open(&dir, &params, params.setup_bytes(), DISK_BYTES, TTL)
+                .unwrap();
+        store.insert("a", get_pub_params(&params)).unwrap();
+        drop(store);
+        assert!(dir.join("a.pp").exists());
+
+        let store = FilePublicParamsStore::open(
+            &dir,
+            &params,
+            params.setup_bytes(),
+            DISK_BYTES,
+            Duration::ZERO,
+        )
+        .unwrap();
+        assert!(!dir.join("a.pp").exists());
+        assert!(store.get("a").is_none());
+
+        // expired files are swept by later inserts, without being looked up
+        let ttl = Duration::from_millis(200);
+        let store =
+            FilePublicParamsStore::open(&dir, &params, 2 * params.setup_bytes(), DISK_BYTES, ttl)
+                .unwrap();
+        store.insert("a", get_pub_params(&params)).unwrap();
+        std::thread::sleep(ttl);
+        store.insert("b", get_pub_params(&params)).unwrap();
+        assert!(!dir.join("a.pp").exists());
+        assert!(dir.join("b.pp").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn file_store_keeps_to_its_budget() {
+        let params = get_params();
+        let file_bytes = get_pub_params(&params).serialize().len();
+        let dir = temp_dir("file-store-budget");
+        let mem_bytes = 4 * params.setup_bytes();
+
+        // the least recently used file goes first
+        let store =
+            FilePublicParamsStore::open(&dir, &params, mem_bytes, 2 * file_bytes, TTL).unwrap();
+        store.insert("a", get_pub_params(&params)).unwrap();
+        store.insert("b", get_pub_params(&params)).unwrap();
+        assert!(store.get("a").is_some());
+        store.insert("c", get_pub_params(&params)).unwrap();
+        assert!(dir.join("a.pp").exists());
+        assert!(!dir.join("b.pp").exists());
+        assert!(dir.join("c.pp").exists());
+        assert!(store.get("b").is_none());
+        assert!(store.remaining_lifetime("b").is_none());
+        drop(store);
+
+        // reopening under a smaller budget keeps the newest files
+        let store = FilePublicParamsStore::open(&dir, &params, mem_bytes, file_bytes, TTL).unwrap();
+        assert!(!dir.join("a.pp").exists());
+        assert!(store.get("c").is_some());
+
+        // an insert over either budget writes nothing
+        let store =
+            FilePublicParamsStore::open(&dir, &params, mem_bytes, file_bytes - 1, TTL).unwrap();
+        assert!(store.insert("d", get_pub_params(&params)).is_err());
+        assert!(store.get("d").is_none());
+        let store =
+            FilePublicParamsStore::open(&dir, &params, params.setup_bytes() - 1, DISK_BYTES, TTL)
+                .unwrap();
+        assert!(store.insert("d", get_pub_params(&params)).is_err());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,8 +1,8 @@
-use crate::{arith::*, client::Seed, params::*, poly::*};
+use crate::{client::Seed, noise_model::*, params::*, poly::*, security::*};
 use rand::{prelude::SmallRng, thread_rng, Rng, SeedableRng};
 use rand_chacha::ChaCha20Rng;
+use serde::Deserialize;
 use serde_json::Value;
-use std::fs;
 
 pub const CFG_20_256: &'static str = r#"
         {'n': 2,
@@ -33,6 +33,16 @@ pub const CFG_16_100000: &'static str = r#"
         'db_item_size': 100000 }
     "#;
 
+// 64-bit FNV-1a, for digests that other implementations need to reproduce easily
+pub fn fnv1a<I: IntoIterator<Item = u8>>(bytes: I) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
 pub fn calc_index(indices: &[usize], lengths: &[usize]) -> usize {
     let mut idx = 0usize;
     let mut prod = 1usize;
@@ -55,7 +65,7 @@ pub fn decompose_index(indices: &mut [usize], index: usize, lengths: &[usize]) {
         cur -= val * prod;
         indices[i] = val;
         if i < lengths.len() - 1 {
-            prod /= lengths[i+1];
+            prod /= lengths[i + 1];
         }
     }
 }
@@ -63,7 +73,7 @@ pub fn decompose_index(indices: &mut [usize], index: usize, lengths: &[usize]) {
 pub fn get_test_params() -> Params {
     Params::init(
         2048,
-        &vec![268369921u64, 249561089u64],
+        &get_default_moduli(2048),
         6.4,
         2,
         256,
@@ -78,12 +88,13 @@ pub fn get_test_params() -> Params {
         1,
         2048,
     )
+    .unwrap()
 }
 
 pub fn get_short_keygen_params() -> Params {
     Params::init(
         2048,
-        &vec![268369921u64, 249561089u64],
+        &get_default_moduli(2048),
         6.4,
         2,
         256,
@@ -98,6 +109,7 @@ pub fn get_short_keygen_params() -> Params {
         1,
         2048,
     )
+    .unwrap()
 }
 
 pub fn get_expansion_testing_params() -> Params {
@@ -134,8 +146,7 @@ pub fn get_fast_expansion_testing_params() -> Params {
     params_from_json(&cfg.replace("'", "\""))
 }
 
-pub fn get_no_expansion_testing_params() -> Params {
-    let cfg = r#"
+pub const CFG_NO_EXPANSION_TESTING: &str = r#"
         {'direct_upload': 1,
         'n': 5,
         'nu_1': 6,
@@ -147,7 +158,9 @@ pub fn get_no_expansion_testing_params() -> Params {
         't_exp_left': 56,
         't_exp_right': 56}
     "#;
-    params_from_json(&cfg.replace("'", "\""))
+
+pub fn get_no_expansion_testing_params() -> Params {
+    params_from_json(&CFG_NO_EXPANSION_TESTING.replace("'", "\""))
 }
 
 pub fn get_seed() -> u64 {
@@ -194,6 +207,7 @@ pub const fn get_empty_params() -> Params {
         barrett_cr_1: [0u64; MAX_MODULI],
         mod0_inv_mod1: 0,
         mod1_inv_mod0: 0,
+        crt_coeffs: [0u64; MAX_MODULI],
         moduli: [0u64; MAX_MODULI],
         modulus: 0,
         modulus_log2: 0,
@@ -201,6 +215,7 @@ pub const fn get_empty_params() -> Params {
         n: 0,
         pt_modulus: 0,
         q2_bits: 0,
+        q2: 0,
         t_conv: 0,
         t_exp_left: 0,
         t_exp_right: 0,
@@ -210,73 +225,44 @@ pub const fn get_empty_params() -> Params {
         db_dim_2: 0,
         instances: 0,
         db_item_size: 0,
+        db_num_items: 0,
     }
 }
 
+pub fn try_params_from_json(cfg: &str) -> Result<Params, ParamsError> {
+    let cfg: ParamsConfig = serde_json::from_str(cfg)?;
+    Params::try_from(cfg)
+}
+
+pub fn try_params_from_json_with_policy(
+    cfg: &str,
+    policy: &SecurityPolicy,
+) -> Result<Params, ParamsError> {
+    let params = try_params_from_json(cfg)?;
+    policy.check(&params)?;
+    Ok(params)
+}
+
+pub fn try_params_from_json_obj(v: &Value) -> Result<Params, ParamsError> {
+    let cfg = ParamsConfig::deserialize(v)?;
+    Params::try_from(cfg)
+}
+
 pub fn params_from_json(cfg: &str) -> Params {
-    let v: Value = serde_json::from_str(cfg).unwrap();
-    params_from_json_obj(&v)
+    try_params_from_json(cfg).unwrap_or_else(|e| panic!("{}", e))
 }
 
 pub fn params_from_json_obj(v: &Value) -> Params {
-    let n = v["n"].as_u64().unwrap() as usize;
-    let db_dim_1 = v["nu_1"].as_u64().unwrap() as usize;
-    let db_dim_2 = v["nu_2"].as_u64().unwrap() as usize;
-    let instances = v["instances"].as_u64().unwrap_or(1) as usize;
-    let p = v["p"].as_u64().unwrap();
-    let q2_bits = u64::max(v["q2_bits"].as_u64().unwrap(), MIN_Q2_BITS);
-    let t_gsw = v["t_gsw"].as_u64().unwrap() as usize;
-    let t_conv = v["t_conv"].as_u64().unwrap() as usize;
-    let t_exp_left = v["t_exp_left"].as_u64().unwrap() as usize;
-    let t_exp_right = v["t_exp_right"].as_u64().unwrap() as usize;
-    let do_expansion = v.get("direct_upload").is_none();
-
-    let mut db_item_size = v["db_item_size"].as_u64().unwrap_or(0) as usize;
-    if db_item_size == 0 {
-        db_item_size = instances * n * n;
-        db_item_size = db_item_size * 2048 * log2_ceil(p) as usize / 8;
-    }
-    Params::init(
-        2048,
-        &vec![268369921u64, 249561089u64],
-        6.4,
-        n,
-        p,
-        q2_bits,
-        t_conv,
-        t_exp_left,
-        t_exp_right,
-        t_gsw,
-        do_expansion,
-        db_dim_1,
-        db_dim_2,
-        instances,
-        db_item_size,
-    )
+    try_params_from_json_obj(v).unwrap_or_else(|e| panic!("{}", e))
 }
 
-static ALL_PARAMS_STORE_FNAME: &str = "../params_store.json";
-
-pub fn get_params_from_store(target_num_log2: usize, item_size: usize) -> Params {
-    let params_store_str = fs::read_to_string(ALL_PARAMS_STORE_FNAME).unwrap();
-    let v: Value = serde_json::from_str(&params_store_str).unwrap();
-    let nearest_target_num = target_num_log2;
-    let nearest_item_size = 1 << usize::max(log2_ceil_usize(item_size), 8);
-    println!(
-        "Starting with parameters for 2^{} x {} bytes...",
-        nearest_target_num, nearest_item_size
-    );
-    let target = v
-        .as_array()
-        .unwrap()
-        .iter()
-        .map(|x| x.as_object().unwrap())
-        .filter(|x| x.get("target_num").unwrap().as_u64().unwrap() == (nearest_target_num as u64))
-        .filter(|x| x.get("item_size").unwrap().as_u64().unwrap() == (nearest_item_size as u64))
-        .map(|x| x.get("params").unwrap())
-        .next()
-        .unwrap();
-    params_from_json_obj(target)
+// Finds parameters for 2^target_num_log2 items of `item_size` bytes with the noise model
+pub fn find_params(target_num_log2: usize, item_size: usize) -> Result<Params, ParamsError> {
+    let num_items = 1 << target_num_log2;
+    select_params(num_items, item_size, DEFAULT_P_ERR_LOG2).ok_or(ParamsError::NotFound {
+        num_items,
+        item_size,
+    })
 }
 
 pub fn read_arbitrary_bits(data: &[u8], bit_offs: usize, num_bits: usize) -> u64 {
@@ -313,13 +299,31 @@ pub fn write_arbitrary_bits(data: &mut [u8], mut val: u64, bit_offs: usize, num_
     }
 }
 
+// The first dimension stores each coefficient with all of its CRT residues packed into one
+// u64, in slots of 64 / crt_count bits.
+pub fn packed_residue_bits(params: &Params) -> usize {
+    64 / params.crt_count
+}
+
+pub fn pack_residues(params: &Params, poly: &[u64], z: usize) -> u64 {
+    let bits = packed_residue_bits(params);
+    let mut val = 0;
+    for n in 0..params.crt_count {
+        val |= (poly[n * params.poly_len + z] % params.moduli[n]) << (n * bits);
+    }
+    val
+}
+
+pub fn unpack_residue(params: &Params, val: u64, n: usize) -> u64 {
+    let bits = packed_residue_bits(params);
+    let mask = u64::MAX >> (64 - bits);
+    (val >> (n * bits)) & mask
+}
+
 pub fn reorient_reg_ciphertexts(params: &Params, out: &mut [u64], v_reg: &Vec<PolyMatrixNTT>) {
     let poly_len = params.poly_len;
     let crt_count = params.crt_count;
 
-    assert_eq!(crt_count, 2);
-    assert!(log2(params.moduli[0]) <= 32);
-
     let num_reg_expanded = 1 << params.db_dim_1;
     let ct_rows = v_reg[0].rows;
     let ct_cols = v_reg[0].cols;
@@ -337,10 +341,8 @@ pub fn reorient_reg_ciphertexts(params: &Params, out: &mut [u64], v_reg: &Vec<Po
                         + j * (ct_cols * ct_rows)
                         + m * (ct_rows)
                         + r;
-                    let val1 = v_reg[j].data[idx_a_in + z] % params.moduli[0];
-                    let val2 = v_reg[j].data[idx_a_in + params.poly_len + z] % params.moduli[1];
-
-                    out[idx_a_out] = val1 | (val2 << 32);
+                    out[idx_a_out] =
+                        pack_residues(params, &v_reg[j].data.as_slice()[idx_a_in..], z);
                 }
             }
         }
@@ -371,7 +373,7 @@ mod test {
         let b = params_from_json(&cfg);
         let c = Params::init(
             2048,
-            &vec![268369921u64, 249561089u64],
+            &get_default_moduli(2048),
             6.4,
             2,
             256,
@@ -385,10 +387,20 @@ mod test {
             6,
             1,
             2048,
-        );
+        )
+        .unwrap();
         assert_eq!(b, c);
     }
 
+    #[test]
+    fn params_to_json_round_trips() {
+        let params = params_from_json(&CFG_16_100000.replace("'", "\""));
+        assert_eq!(params_from_json(&params.to_json()), params);
+
+        let params = get_no_expansion_testing_params();
+        assert_eq!(params_from_json(&params.to_json()), params);
+    }
+
     #[test]
     fn test_decompose_calc_correct() {
         let lengths = [5, 4, 3];